@@ -0,0 +1,28 @@
+use async_trait::async_trait;
+
+use crate::auth::AuthProvider;
+use crate::context::{Auth, Context};
+use crate::error::Error;
+
+/// Sends an API key as the `apicurio_registry_api_key` query parameter of every request.
+pub struct ApiKeyQueryProvider {
+    key: String,
+}
+
+impl ApiKeyQueryProvider {
+    pub fn new(key: impl ToString) -> Self {
+        Self {
+            key: key.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for ApiKeyQueryProvider {
+    async fn login(&self, mut ctx: Context) -> Result<Context, Error> {
+        ctx.set_auth(Auth::ApiKeyQuery {
+            key: self.key.clone(),
+        });
+        Ok(ctx)
+    }
+}
@@ -0,0 +1,79 @@
+use std::process::Command;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::auth::AuthProvider;
+use crate::context::{Auth, Context};
+use crate::error::Error;
+
+/// Gets credentials from an external command, called with the registry URL as its only argument.
+///
+/// The command prints either `{"username": "...", "password": "..."}` or `{"token": "..."}`
+/// as JSON on stdout. Credentials obtained this way are never written to the context file.
+pub struct HelperProvider {
+    helper: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct HelperOutput {
+    username: Option<String>,
+    password: Option<String>,
+    token: Option<String>,
+}
+
+impl HelperProvider {
+    pub fn new(helper: impl ToString) -> Self {
+        Self {
+            helper: helper.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for HelperProvider {
+    async fn login(&self, mut ctx: Context) -> Result<Context, Error> {
+        let output = Command::new(&self.helper)
+            .arg(ctx.registry_url.as_str())
+            .output()
+            .map_err(|err| {
+                Error::Auth(format!("Failed to run auth helper {}: {}", self.helper, err).into())
+            })?;
+        if !output.status.success() {
+            return Err(Error::Auth(
+                format!(
+                    "Auth helper {} failed with {}: {}",
+                    self.helper,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )
+                .into(),
+            ));
+        }
+
+        let credentials: HelperOutput = serde_json::from_slice(&output.stdout).map_err(|err| {
+            Error::Auth(format!("Invalid output from auth helper {}: {}", self.helper, err).into())
+        })?;
+        let auth = match credentials {
+            HelperOutput {
+                token: Some(token), ..
+            } => Auth::Bearer { token },
+            HelperOutput {
+                username: Some(username),
+                password,
+                ..
+            } => Auth::Basic { username, password },
+            _ => {
+                return Err(Error::Auth(
+                    format!(
+                        "Auth helper {} returned neither a token nor a username",
+                        self.helper
+                    )
+                    .into(),
+                ))
+            }
+        };
+        ctx.set_auth(auth);
+        Ok(ctx)
+    }
+}
@@ -3,7 +3,9 @@ use async_trait::async_trait;
 use crate::context::Context;
 use crate::error::Error;
 
+pub mod apikey;
 pub mod basic;
+pub mod helper;
 pub mod oidc;
 
 #[async_trait]
@@ -1,4 +1,7 @@
 use std::convert::Infallible;
+use std::fmt::Display;
+use std::future::Future;
+use std::io::ErrorKind;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::ops::Add;
 use std::sync::Arc;
@@ -8,13 +11,16 @@ use chrono::{DateTime, Duration, Utc};
 use http::StatusCode;
 use openidconnect::{
     AuthorizationCode, ClientId, ClientSecret, CsrfToken, IssuerUrl, Nonce, OAuth2TokenResponse,
-    RedirectUrl, RequestTokenError, Scope,
+    RedirectUrl, RefreshToken, RequestTokenError, Scope, TokenResponse,
+};
+use openidconnect::core::{
+    CoreAuthenticationFlow, CoreClient, CoreIdTokenClaims, CoreProviderMetadata,
 };
-use openidconnect::core::{CoreAuthenticationFlow, CoreClient, CoreProviderMetadata};
 use serde::Deserialize;
 use tokio::sync::{mpsc, RwLock};
 use tokio::sync::mpsc::Sender;
 use warp::{Filter, Reply};
+use warp::reply::{Html, WithStatus};
 
 use crate::auth::AuthProvider;
 use crate::context::{Auth, Context};
@@ -31,11 +37,16 @@ pub struct TokenSet {
 pub struct OidcProvider {
     issuer_url: String,
     client_id: String,
+    #[allow(dead_code)]
     client_secret: Option<String>,
     scopes: Vec<Scope>,
     client: CoreClient,
     tokens: Option<TokenSet>,
+    csrf_token: Option<CsrfToken>,
+    nonce: Option<Nonce>,
+    claims: Option<CoreIdTokenClaims>,
     port: u16,
+    login_timeout: std::time::Duration,
 }
 
 impl OidcProvider {
@@ -45,13 +56,13 @@ impl OidcProvider {
         client_secret: Option<impl ToString>,
         scope: impl ToString,
         port: u16,
+        login_timeout: std::time::Duration,
     ) -> Result<Self, Error> {
         let metadata = CoreProviderMetadata::discover_async(
             IssuerUrl::new(issuer_url.to_string())?,
             openidconnect::reqwest::async_http_client,
         )
-        .await
-        .map_err(|err| Error::Auth(err.into()))?;
+        .await?;
         let client_secret = client_secret.map(|secret| secret.to_string());
         let scopes = scope
             .to_string()
@@ -68,23 +79,69 @@ impl OidcProvider {
                     .as_ref()
                     .map(|secret| ClientSecret::new(secret.to_string())),
             )
-            .set_redirect_uri(RedirectUrl::new(format!(
-                "http://localhost:{}/callback",
-                port
-            ))?),
+            .set_redirect_uri(redirect_url(port)?),
             client_secret,
             scopes,
             tokens: None,
+            csrf_token: None,
+            nonce: None,
+            claims: None,
             port,
+            login_timeout,
         })
     }
 }
 
+/// Exchanges a refresh token for new tokens. Providers that do not rotate refresh tokens
+/// return none, in which case the current one keeps being used.
+pub async fn refresh_tokens(
+    issuer_url: &str,
+    client_id: &str,
+    refresh_token: &str,
+) -> Result<TokenSet, Error> {
+    let metadata = CoreProviderMetadata::discover_async(
+        IssuerUrl::new(issuer_url.to_string())?,
+        openidconnect::reqwest::async_http_client,
+    )
+    .await?;
+    let client =
+        CoreClient::from_provider_metadata(metadata, ClientId::new(client_id.to_string()), None);
+    let token_response = client
+        .exchange_refresh_token(&RefreshToken::new(refresh_token.to_string()))
+        .request_async(openidconnect::reqwest::async_http_client)
+        .await?;
+    Ok(TokenSet {
+        access_token: token_response.access_token().secret().clone(),
+        refresh_token: token_response
+            .refresh_token()
+            .map(|token| token.secret().clone())
+            .or_else(|| Some(refresh_token.to_string())),
+        expires_at: Utc::now().add(
+            token_response
+                .expires_in()
+                .map(|duration| Duration::from_std(duration).expect("Duration::from_std"))
+                .unwrap_or_else(|| Duration::seconds(0)),
+        ),
+    })
+}
+
 #[async_trait]
 impl AuthProvider for OidcProvider {
     async fn login(&self, mut ctx: Context) -> Result<Context, Error> {
+        let this = Arc::new(RwLock::new(self.clone()));
+        let (port, server) = bind_callback_server(this.clone(), self.port)?;
+        tracing::info!("Using port {} for OIDC callback server", port);
+        let mut provider = this.write().await;
+        if port != self.port {
+            provider.client = provider
+                .client
+                .clone()
+                .set_redirect_uri(redirect_url(port)?);
+            provider.port = port;
+        }
+
         // Generate the full authorization URL.
-        let mut req = self.client.authorize_url(
+        let mut req = provider.client.authorize_url(
             CoreAuthenticationFlow::AuthorizationCode,
             CsrfToken::new_random,
             Nonce::new_random,
@@ -92,31 +149,42 @@ impl AuthProvider for OidcProvider {
         for scope in self.scopes.clone() {
             req = req.add_scope(scope);
         }
+        if !self
+            .scopes
+            .iter()
+            .any(|scope| scope.as_str() == "offline_access")
+        {
+            tracing::warn!("The OIDC scope does not include offline_access, so the provider may not issue a refresh token and you will have to log in again when the access token expires");
+        }
 
-        let (auth_url, _csrf_token, _nonce) = req.url();
+        let (auth_url, csrf_token, nonce) = req.url();
 
-        let this = Arc::new(RwLock::new(self.clone()));
-        let (tx, mut rx) = mpsc::channel(1);
-        let app = warp::get()
-            .and(warp::path("callback"))
-            .and(with_provider(this.clone()))
-            .and(with_shutdown_signal(tx))
-            .and(warp::query::query::<CallbackQuery>())
-            .and_then(callback_handler);
+        provider.csrf_token = Some(csrf_token);
+        provider.nonce = Some(nonce);
+        drop(provider);
 
-        let (_addr, server) = warp::serve(app).bind_with_graceful_shutdown(
-            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), self.port),
-            async move {
-                rx.recv().await.expect("shutdown::recv");
-            },
-        );
-
-        open::that(&auth_url.to_string())?;
+        open::that(auth_url.to_string())?;
         tracing::info!("The login page has been opened on your default browser. You can also manually visit {}", auth_url);
-        server.await;
+        // Dropping the server future on timeout shuts the callback server down
+        if tokio::time::timeout(self.login_timeout, server)
+            .await
+            .is_err()
+        {
+            return Err(Error::Auth("OIDC login timed out; please try again".into()));
+        }
 
         let this = this.read().await;
-        let tokens = this.tokens.as_ref().unwrap();
+        let tokens = this
+            .tokens
+            .as_ref()
+            .ok_or_else(|| Error::Auth("OIDC login failed".into()))?;
+        if let Some(claims) = &this.claims {
+            let user = claims
+                .email()
+                .map(|email| email.as_str())
+                .unwrap_or_else(|| claims.subject().as_str());
+            tracing::info!("Logged in as {}", user);
+        }
         ctx.set_auth(Auth::Oidc {
             issuer_url: this.issuer_url.clone(),
             client_id: this.client_id.clone(),
@@ -128,6 +196,50 @@ impl AuthProvider for OidcProvider {
     }
 }
 
+fn redirect_url(port: u16) -> Result<RedirectUrl, Error> {
+    RedirectUrl::new(format!("http://localhost:{}/callback", port)).map_err(Into::into)
+}
+
+/// Binds the callback server to the first port, starting from `port` and up to 10 ports higher,
+/// that is not already in use. The server keeps the port it was bound to, so no other process
+/// can take it in between.
+fn bind_callback_server(
+    provider: Arc<RwLock<OidcProvider>>,
+    port: u16,
+) -> Result<(u16, impl Future<Output = ()>), Error> {
+    let last = port.saturating_add(10);
+    let mut last_err = None;
+    for candidate in port..=last {
+        let (tx, mut rx) = mpsc::channel(1);
+        let app = warp::get()
+            .and(warp::path("callback"))
+            .and(with_provider(provider.clone()))
+            .and(with_shutdown_signal(tx))
+            .and(warp::query::query::<CallbackQuery>())
+            .and_then(callback_handler);
+        let bound = warp::serve(app).try_bind_with_graceful_shutdown(
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), candidate),
+            async move {
+                rx.recv().await.expect("shutdown::recv");
+            },
+        );
+        match bound {
+            Ok((_addr, server)) => return Ok((candidate, server)),
+            // warp does not expose the underlying IO error, so any failure moves on to the next port
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(Error::Io(std::io::Error::new(
+        ErrorKind::AddrInUse,
+        format!(
+            "OIDC callback server could not start on ports {} to {}: {}",
+            port,
+            last,
+            last_err.expect("at least one port tried")
+        ),
+    )))
+}
+
 fn with_provider(
     provider: Arc<RwLock<OidcProvider>>,
 ) -> impl Filter<Extract = (Arc<RwLock<OidcProvider>>,), Error = Infallible> + Clone {
@@ -143,9 +255,19 @@ fn with_shutdown_signal(
 async fn callback_handler(
     provider: Arc<RwLock<OidcProvider>>,
     tx: Sender<()>,
-    CallbackQuery { code, state: _state }: CallbackQuery,
+    CallbackQuery { code, state }: CallbackQuery,
 ) -> Result<impl Reply, warp::Rejection> {
     let mut provider = provider.write().await;
+    let expected_state = provider.csrf_token.as_ref().map(|token| token.secret());
+    if expected_state != Some(&state) {
+        tracing::info!("ERROR: invalid state parameter in OIDC callback");
+        tx.send(()).await.expect("shutdown::send");
+        return Ok(error_reply(
+            "Invalid state",
+            "The authentication response does not match the login request",
+        ));
+    }
+
     let token_response = provider
         .client
         .exchange_code(AuthorizationCode::new(code))
@@ -160,20 +282,27 @@ async fn callback_handler(
         };
         tracing::info!("ERROR: {} {}", err, msg);
         tx.send(()).await.expect("shutdown::send");
-        return Ok(warp::reply::with_status(
-            warp::reply::html(format!(
-                r#"
-        <h1>ERROR</h1>
-        <h2>{}</h2>
-        <p>{}</p>
-"#,
-                err, msg
-            )),
-            StatusCode::BAD_REQUEST,
-        ));
+        return Ok(error_reply(err, msg));
     }
 
     let token_response = token_response.unwrap();
+    if let Some(id_token) = token_response.id_token() {
+        let claims = {
+            let nonce = provider.nonce.as_ref().expect("OIDC nonce");
+            let verifier = provider.client.id_token_verifier();
+            id_token.claims(&verifier, nonce).cloned()
+        };
+        match claims {
+            Ok(claims) => provider.claims = Some(claims),
+            Err(err) => {
+                tracing::info!("ERROR: invalid ID token: {}", err);
+                tx.send(()).await.expect("shutdown::send");
+                return Ok(error_reply("Invalid ID token", err));
+            }
+        }
+    } else {
+        tracing::debug!("The token response contains no ID token, skipping nonce verification");
+    }
     provider.tokens = Some(TokenSet {
         access_token: token_response.access_token().secret().clone(),
         refresh_token: token_response
@@ -194,6 +323,20 @@ async fn callback_handler(
         StatusCode::OK,
     ))
 }
+fn error_reply(err: impl Display, msg: impl Display) -> WithStatus<Html<String>> {
+    warp::reply::with_status(
+        warp::reply::html(format!(
+            r#"
+        <h1>ERROR</h1>
+        <h2>{}</h2>
+        <p>{}</p>
+"#,
+            err, msg
+        )),
+        StatusCode::BAD_REQUEST,
+    )
+}
+
 #[derive(Debug, Deserialize)]
 struct CallbackQuery {
     code: String,
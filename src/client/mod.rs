@@ -1,28 +1,184 @@
-use reqwest::ClientBuilder;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use reqwest::{Certificate, Proxy};
 use url::Url;
 
 use crate::client::v2::ClientV2;
+use crate::error::Error;
 
+mod util;
 mod v2;
 
+pub use v2::{classify_http_error, HttpErrorKind};
+
+const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 10;
+pub const DEFAULT_LARGE_ARTIFACT_THRESHOLD_BYTES: usize = 1024 * 1024;
+/// Items requested per page when walking a paginated endpoint to the end.
+pub const DEFAULT_FETCH_PAGE_SIZE: usize = 100;
+
 #[derive(Clone)]
 pub struct Client {
     base_url: Url,
     client: reqwest::Client,
+    extra_headers: HashMap<String, String>,
+    retries: u32,
+    large_artifact_threshold_bytes: usize,
+    fetch_page_size: usize,
 }
 
 impl Client {
-    pub fn new(base_url: Url) -> Self {
-        Client {
+    #[allow(dead_code)]
+    pub fn new(base_url: Url, extra_headers: HashMap<String, String>) -> Self {
+        Client::builder(base_url)
+            .extra_headers(extra_headers)
+            .build()
+            .expect("ClientBuilder::build")
+    }
+
+    pub fn builder(base_url: Url) -> ClientBuilder {
+        ClientBuilder {
             base_url,
-            client: ClientBuilder::new()
-                .use_rustls_tls()
-                .build()
-                .expect("ClientBuilder::build"),
+            timeout: None,
+            retries: 0,
+            ca_cert: None,
+            proxy: None,
+            no_verify_tls: false,
+            extra_headers: HashMap::new(),
+            pool_max_idle_per_host: DEFAULT_POOL_MAX_IDLE_PER_HOST,
+            pool_idle_timeout: None,
+            large_artifact_threshold_bytes: DEFAULT_LARGE_ARTIFACT_THRESHOLD_BYTES,
+            fetch_page_size: DEFAULT_FETCH_PAGE_SIZE,
         }
     }
 
+    /// Downloads content hosted outside of the registry, e.g. push artifacts served by a CDN.
+    pub async fn fetch_url(&self, url: &Url) -> Result<Vec<u8>, Error> {
+        self.v2().fetch_url(url).await
+    }
+
     pub fn v2(&self) -> ClientV2 {
-        ClientV2::new(self.base_url.clone(), self.client.clone())
+        ClientV2::new(
+            self.base_url.clone(),
+            self.client.clone(),
+            self.extra_headers.clone(),
+            self.retries,
+            self.large_artifact_threshold_bytes,
+            self.fetch_page_size,
+        )
+    }
+}
+
+pub struct ClientBuilder {
+    base_url: Url,
+    timeout: Option<Duration>,
+    retries: u32,
+    ca_cert: Option<PathBuf>,
+    proxy: Option<Url>,
+    no_verify_tls: bool,
+    extra_headers: HashMap<String, String>,
+    pool_max_idle_per_host: usize,
+    pool_idle_timeout: Option<Duration>,
+    large_artifact_threshold_bytes: usize,
+    fetch_page_size: usize,
+}
+
+impl ClientBuilder {
+    /// Timeout of every single request, from connection to the end of the response body.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// How many times to retry requests that failed because of connection problems or gateway errors.
+    pub fn retry(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// PEM encoded certificate to trust in addition to the system ones.
+    pub fn ca_cert(mut self, path: PathBuf) -> Self {
+        self.ca_cert = Some(path);
+        self
+    }
+
+    pub fn proxy(mut self, proxy: Url) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Accept any TLS certificate. Only meant for local testing.
+    pub fn no_verify_tls(mut self, no_verify_tls: bool) -> Self {
+        self.no_verify_tls = no_verify_tls;
+        self
+    }
+
+    pub fn extra_headers(mut self, extra_headers: HashMap<String, String>) -> Self {
+        self.extra_headers = extra_headers;
+        self
+    }
+
+    /// How many idle connections to the registry to keep open for reuse. Pulls fetch artifacts
+    /// concurrently, so keeping enough connections around saves a TLS handshake per artifact
+    /// at the cost of some open sockets. 0 disables connection reuse.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = max;
+        self
+    }
+
+    /// How long an idle connection is kept open before being closed.
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Artifacts larger than this are streamed to the registry instead of being read in memory first.
+    /// Streamed pushes cannot be retried.
+    pub fn large_artifact_threshold_bytes(mut self, bytes: usize) -> Self {
+        self.large_artifact_threshold_bytes = bytes;
+        self
+    }
+
+    /// How many items to request per page when listing every artifact of a group or version of an artifact.
+    /// Must be greater than 0.
+    #[allow(dead_code)]
+    pub fn fetch_page_size(mut self, size: usize) -> Self {
+        self.fetch_page_size = size;
+        self
+    }
+
+    pub fn build(self) -> Result<Client, Error> {
+        let mut builder = reqwest::ClientBuilder::new()
+            .use_rustls_tls()
+            .danger_accept_invalid_certs(self.no_verify_tls)
+            .pool_max_idle_per_host(self.pool_max_idle_per_host);
+        if let Some(timeout) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(path) = &self.ca_cert {
+            let pem = std::fs::read(path).map_err(|err| {
+                Error::setup(format!(
+                    "Failed to read CA certificate {}: {}",
+                    path.display(),
+                    err
+                ))
+            })?;
+            builder = builder.add_root_certificate(Certificate::from_pem(&pem)?);
+        }
+        if let Some(proxy) = self.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?);
+        }
+        Ok(Client {
+            base_url: self.base_url,
+            client: builder.build()?,
+            extra_headers: self.extra_headers,
+            retries: self.retries,
+            large_artifact_threshold_bytes: self.large_artifact_threshold_bytes,
+            fetch_page_size: self.fetch_page_size,
+        })
     }
 }
@@ -0,0 +1,26 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use http::header;
+
+/// Reads how long the registry asks to wait before the next request, from a `Retry-After` header
+/// holding either a number of seconds or an HTTP date.
+pub fn parse_retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let value = response
+        .headers()
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    // A date in the past means the request can be retried right away
+    Some(
+        at.with_timezone(&Utc)
+            .signed_duration_since(Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
@@ -1,30 +1,116 @@
 use std::collections::HashMap;
+use std::time::Duration;
 
 use async_trait::async_trait;
+use chrono::Utc;
 use http::header;
-use reqwest::RequestBuilder;
+use reqwest::{Body, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use tokio::io::AsyncReadExt;
+use tokio_util::io::ReaderStream;
 use url::Url;
 
+use crate::client::util::parse_retry_after;
 use crate::{context, provider};
 use crate::context::Auth;
 use crate::error::Error;
-use crate::provider::{ArtifactType, Provider, PushArtifactMetadata};
+use crate::provider::{
+    ArtifactExistence, ArtifactReference, ArtifactState, ArtifactType, Provider,
+    PushArtifactMetadata, PushContent,
+};
+
+/// Longest `Retry-After` delay honored, so a misbehaving registry cannot stall a sync indefinitely.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
 
 /// Client for Apicurio Registry API v2
 /// https://www.apicur.io/registry/docs/apicurio-registry/2.0.1.Final/assets-attachments/registry-rest-api.htm
-
+#[derive(Clone)]
 pub struct ClientV2 {
     base_url: Url,
     client: reqwest::Client,
+    extra_headers: HashMap<String, String>,
+    retries: u32,
+    large_artifact_threshold_bytes: usize,
+    fetch_page_size: usize,
 }
 
 impl ClientV2 {
-    pub(super) fn new(base_url: Url, client: reqwest::Client) -> Self {
+    pub(super) fn new(
+        base_url: Url,
+        client: reqwest::Client,
+        extra_headers: HashMap<String, String>,
+        retries: u32,
+        large_artifact_threshold_bytes: usize,
+        fetch_page_size: usize,
+    ) -> Self {
         Self {
             base_url: base_url.join("apis/registry/v2/").unwrap(),
             client,
+            extra_headers,
+            retries,
+            large_artifact_threshold_bytes,
+            fetch_page_size,
+        }
+    }
+
+    /// Sends the request, retrying it up to `retries` times on connection failures,
+    /// timeouts and gateway errors. Rate limited requests are retried after the delay
+    /// the registry asks for, if any, at least once even when `retries` is 0.
+    ///
+    /// Requests with a streamed body, like pushes of large artifacts, cannot be cloned
+    /// and are never retried.
+    async fn send(&self, mut req: RequestBuilder) -> reqwest::Result<Response> {
+        let attempts = self.retries.max(1);
+        for attempt in 1..=attempts {
+            let next = match req.try_clone() {
+                Some(next) => next,
+                None => break,
+            };
+            let backoff = Duration::from_millis(500 * u64::from(attempt));
+            let res = req.send().await;
+            let delay = match &res {
+                Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    let delay = parse_retry_after(response)
+                        .map_or(backoff, |delay| delay.min(MAX_RETRY_AFTER));
+                    tracing::warn!(
+                        "Rate limited by the registry, retrying in {}s ({}/{})",
+                        delay.as_secs_f32(),
+                        attempt,
+                        attempts
+                    );
+                    delay
+                }
+                Ok(response) if !is_transient(response.status()) => return res,
+                Err(err) if !classify_http_error(err).is_retryable() => return res,
+                _ if attempt <= self.retries => {
+                    tracing::debug!("Request failed, retrying ({}/{})", attempt, self.retries);
+                    backoff
+                }
+                _ => return res,
+            };
+            tokio::time::sleep(delay).await;
+            req = next;
         }
+        req.send().await
+    }
+
+    /// Applies the context authentication and extra headers to the request.
+    fn with_context(&self, req: RequestBuilder, auth: &context::Auth) -> RequestBuilder {
+        self.extra_headers
+            .iter()
+            .fold(with_auth(req, auth), |req, (name, value)| {
+                req.header(name, value)
+            })
+    }
+
+    /// Downloads `url` outside of the registry API, with the same timeouts, proxy, TLS and retry
+    /// settings. No credentials or extra headers are sent, as the URL may point anywhere.
+    pub(super) async fn fetch_url(&self, url: &Url) -> Result<Vec<u8>, Error> {
+        let req = self.client.get(url.clone());
+        tracing::debug!("{:?}", req);
+        let res = error_for_status(self.send(req).await?).await?;
+        Ok(res.bytes().await?.to_vec())
     }
 }
 
@@ -35,10 +121,11 @@ impl Provider for ClientV2 {
             .client
             .get(self.base_url.join("system/info").unwrap())
             .header(header::ACCEPT, "application/json");
-        let req = with_auth(req, auth);
+        let req = self.with_context(req, auth);
         tracing::debug!("{:?}", req);
 
-        let res: reqwest::Result<SystemInfo> = req.send().await?.error_for_status()?.json().await;
+        let res = error_for_status(self.send(req).await?).await?;
+        let res: reqwest::Result<SystemInfo> = res.json().await;
         res.map(Into::into).map_err(Into::into)
     }
 
@@ -59,11 +146,11 @@ impl Provider for ClientV2 {
                     .unwrap(),
             )
             .header(header::ACCEPT, "application/json");
-        let req = with_auth(req, auth);
+        let req = self.with_context(req, auth);
         tracing::debug!("{:?}", req);
 
-        let res: reqwest::Result<ArtifactMetadata> =
-            req.send().await?.error_for_status()?.json().await;
+        let res = artifact_error_for_status(self.send(req).await?).await?;
+        let res: reqwest::Result<ArtifactMetadata> = res.json().await;
         res.map(Into::into).map_err(Into::into)
     }
 
@@ -85,11 +172,11 @@ impl Provider for ClientV2 {
                     .unwrap(),
             )
             .header(header::ACCEPT, "application/json");
-        let req = with_auth(req, auth);
+        let req = self.with_context(req, auth);
         tracing::debug!("{:?}", req);
 
-        let res: reqwest::Result<ArtifactVersionMetadata> =
-            req.send().await?.error_for_status()?.json().await;
+        let res = artifact_error_for_status(self.send(req).await?).await?;
+        let res: reqwest::Result<ArtifactVersionMetadata> = res.json().await;
         res.map(Into::into).map_err(Into::into)
     }
 
@@ -111,19 +198,41 @@ impl Provider for ClientV2 {
                     .unwrap(),
             )
             .header(header::ACCEPT, "application/json");
-        let req = with_auth(req, auth);
+        let req = self.with_context(req, auth);
         tracing::debug!("{:?}", req);
 
-        let body = req.send().await?.error_for_status()?.bytes().await?;
+        let res = artifact_error_for_status(self.send(req).await?).await?;
+        let body = res.bytes().await?;
+        Ok(body.to_vec())
+    }
+
+    async fn fetch_artifact_by_sha256(
+        &self,
+        hash: &str,
+        auth: &context::Auth,
+    ) -> Result<Vec<u8>, Error> {
+        let req = self
+            .client
+            .get(
+                self.base_url
+                    .join(&format!("ids/contentHashes/{}", hash))
+                    .unwrap(),
+            )
+            .header(header::ACCEPT, "application/json");
+        let req = self.with_context(req, auth);
+        tracing::debug!("{:?}", req);
+
+        let res = artifact_error_for_status(self.send(req).await?).await?;
+        let body = res.bytes().await?;
         Ok(body.to_vec())
     }
 
     async fn push_artifact(
         &self,
         metadata: PushArtifactMetadata,
-        content: Vec<u8>,
+        mut content: PushContent,
         auth: &context::Auth,
-    ) -> Result<(), Error> {
+    ) -> Result<provider::ArtifactMetadata, Error> {
         let req = self
             .client
             .post(
@@ -132,7 +241,7 @@ impl Provider for ClientV2 {
                     .unwrap(),
             )
             .header(header::ACCEPT, "application/json");
-        let req = with_auth(req, auth);
+        let req = self.with_context(req, auth);
 
         let req = if let Some(typ) = metadata.artifact_type {
             req.header("X-Registry-ArtifactType", typ.to_string())
@@ -140,14 +249,48 @@ impl Provider for ClientV2 {
             req
         };
 
+        let bump_version = metadata.bump_version;
+        let version = metadata
+            .version
+            .clone()
+            .or_else(|| bump_version.then(|| Utc::now().format("UTC_%Y%m%d%H%M%S").to_string()));
+        let req = if let Some(version) = &version {
+            req.header("X-Registry-Version", version)
+        } else {
+            req
+        };
+
+        // Small artifacts are sent from memory, so that the request can be retried
+        let body = if content.len > self.large_artifact_threshold_bytes as u64 {
+            tracing::debug!(
+                "Streaming {} bytes for {}/{}",
+                content.len,
+                metadata.group_id,
+                metadata.artifact_id
+            );
+            Body::wrap_stream(ReaderStream::new(content.reader))
+        } else {
+            let mut bytes = Vec::with_capacity(content.len as usize);
+            content.reader.read_to_end(&mut bytes).await?;
+            Body::from(bytes)
+        };
         let req = req
             .header("X-Registry-ArtifactId", &metadata.artifact_id)
+            .header(header::CONTENT_LENGTH, content.len)
             .query(&[("ifExists", "RETURN_OR_UPDATE")])
-            .body(content);
+            .body(body);
         tracing::debug!("{:?}", req);
 
-        req.send().await?.error_for_status()?;
+        let res = artifact_error_for_status(self.send(req).await?).await?;
+        let res: reqwest::Result<ArtifactMetadata> = res.json().await;
+        let mut pushed: provider::ArtifactMetadata = res?.into();
 
+        let body = UpdateArtifactMetadataBody {
+            name: metadata.name,
+            description: metadata.description,
+            labels: metadata.labels,
+            properties: metadata.properties,
+        };
         let req = self
             .client
             .put(
@@ -159,24 +302,312 @@ impl Provider for ClientV2 {
                     .unwrap(),
             )
             .header(header::ACCEPT, "application/json")
-            .json(&UpdateArtifactMetadataBody {
-                name: metadata.name,
-                description: metadata.description,
-                labels: metadata.labels,
-                properties: metadata.properties,
+            .json(&body);
+        let req = self.with_context(req, auth);
+        tracing::debug!("{:?}", req);
+
+        artifact_error_for_status(self.send(req).await?).await?;
+        // The registry replaces the whole metadata with the body of the update
+        pushed.name = body.name;
+        pushed.description = body.description;
+        pushed.labels = body.labels.unwrap_or_default();
+        pushed.properties = body.properties.unwrap_or_default();
+        Ok(pushed)
+    }
+
+    async fn fetch_all_artifact_metadata(
+        &self,
+        group_id: &str,
+        auth: &context::Auth,
+    ) -> Result<Vec<provider::SearchedArtifact>, Error> {
+        let url = self
+            .base_url
+            .join(&format!("groups/{}/artifacts", group_id))
+            .unwrap();
+        let artifacts: Vec<SearchedArtifact> =
+            fetch_all_pages(self, url, auth, self.fetch_page_size).await?;
+        Ok(artifacts.into_iter().map(Into::into).collect())
+    }
+
+    async fn list_artifact_versions(
+        &self,
+        group_id: &str,
+        artifact_id: &str,
+        auth: &context::Auth,
+    ) -> Result<Vec<String>, Error> {
+        let url = self
+            .base_url
+            .join(&format!(
+                "groups/{}/artifacts/{}/versions",
+                group_id, artifact_id
+            ))
+            .unwrap();
+        let versions: Vec<SearchedVersion> =
+            fetch_all_pages(self, url, auth, self.fetch_page_size).await?;
+        Ok(versions
+            .into_iter()
+            .map(|version| version.version)
+            .collect())
+    }
+
+    async fn fetch_artifact_references(
+        &self,
+        group_id: &str,
+        artifact_id: &str,
+        version: &str,
+        auth: &context::Auth,
+    ) -> Result<Vec<ArtifactReference>, Error> {
+        let req = self
+            .client
+            .get(
+                self.base_url
+                    .join(&format!(
+                        "groups/{}/artifacts/{}/versions/{}/references",
+                        group_id, artifact_id, version
+                    ))
+                    .unwrap(),
+            )
+            .header(header::ACCEPT, "application/json");
+        let req = self.with_context(req, auth);
+        tracing::debug!("{:?}", req);
+
+        let res = artifact_error_for_status(self.send(req).await?).await?;
+        let references: Vec<Reference> = res.json().await?;
+        Ok(references.into_iter().map(Into::into).collect())
+    }
+
+    async fn delete_artifact(
+        &self,
+        group_id: &str,
+        artifact_id: &str,
+        auth: &context::Auth,
+    ) -> Result<(), Error> {
+        let req = self.client.delete(
+            self.base_url
+                .join(&format!("groups/{}/artifacts/{}", group_id, artifact_id))
+                .unwrap(),
+        );
+        let req = self.with_context(req, auth);
+        tracing::debug!("{:?}", req);
+
+        error_for_status(self.send(req).await?).await?;
+        Ok(())
+    }
+
+    async fn set_artifact_version_state(
+        &self,
+        group_id: &str,
+        artifact_id: &str,
+        version: &str,
+        state: ArtifactState,
+        auth: &context::Auth,
+    ) -> Result<(), Error> {
+        let req = self
+            .client
+            .put(
+                self.base_url
+                    .join(&format!(
+                        "groups/{}/artifacts/{}/versions/{}/state",
+                        group_id, artifact_id, version
+                    ))
+                    .unwrap(),
+            )
+            .json(&UpdateStateBody { state });
+        let req = self.with_context(req, auth);
+        tracing::debug!("{:?}", req);
+
+        artifact_error_for_status(self.send(req).await?).await?;
+        Ok(())
+    }
+
+    async fn artifact_exists(
+        &self,
+        group_id: &str,
+        artifact_id: &str,
+        auth: &context::Auth,
+    ) -> Result<ArtifactExistence, Error> {
+        let req = self.client.head(
+            self.base_url
+                .join(&format!("groups/{}/artifacts/{}", group_id, artifact_id))
+                .unwrap(),
+        );
+        let req = self.with_context(req, auth);
+        tracing::debug!("{:?}", req);
+
+        let res = self.send(req).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(ArtifactExistence {
+                exists: false,
+                version: None,
+            });
+        }
+        let res = artifact_error_for_status(res).await?;
+        let version = res
+            .headers()
+            .get("X-Registry-Version")
+            .and_then(|version| version.to_str().ok())
+            .map(str::to_string);
+        Ok(ArtifactExistence {
+            exists: true,
+            version,
+        })
+    }
+
+    async fn fetch_group_metadata(
+        &self,
+        group_id: &str,
+        auth: &context::Auth,
+    ) -> Result<provider::GroupMetadata, Error> {
+        let req = self
+            .client
+            .get(self.base_url.join(&format!("groups/{}", group_id)).unwrap())
+            .header(header::ACCEPT, "application/json");
+        let req = self.with_context(req, auth);
+        tracing::debug!("{:?}", req);
+
+        let res = artifact_error_for_status(self.send(req).await?).await?;
+        let res: reqwest::Result<GroupMetadata> = res.json().await;
+        res.map(Into::into).map_err(Into::into)
+    }
+
+    async fn create_group(
+        &self,
+        group_id: &str,
+        description: Option<String>,
+        auth: &context::Auth,
+    ) -> Result<provider::GroupMetadata, Error> {
+        let req = self
+            .client
+            .post(self.base_url.join("groups").unwrap())
+            .header(header::ACCEPT, "application/json")
+            .json(&CreateGroupBody {
+                id: group_id.to_string(),
+                description,
             });
-        let req = with_auth(req, auth);
+        let req = self.with_context(req, auth);
+        tracing::debug!("{:?}", req);
+
+        let res = error_for_status(self.send(req).await?).await?;
+        let res: reqwest::Result<GroupMetadata> = res.json().await;
+        res.map(Into::into).map_err(Into::into)
+    }
+
+    async fn delete_group(&self, group_id: &str, auth: &context::Auth) -> Result<(), Error> {
+        let req = self
+            .client
+            .delete(self.base_url.join(&format!("groups/{}", group_id)).unwrap());
+        let req = self.with_context(req, auth);
         tracing::debug!("{:?}", req);
 
-        req.send().await?.error_for_status()?;
+        artifact_error_for_status(self.send(req).await?).await?;
         Ok(())
     }
 }
 
+/// What a failed request most likely went wrong with, to tell users where to look.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpErrorKind {
+    /// The registry could not be reached, or did not answer in time.
+    Network,
+    /// The registry rejected the credentials.
+    Auth,
+    NotFound,
+    ServerError,
+    Unknown,
+}
+
+impl HttpErrorKind {
+    /// Whether sending the same request again may succeed.
+    pub fn is_retryable(self) -> bool {
+        matches!(self, HttpErrorKind::Network | HttpErrorKind::ServerError)
+    }
+}
+
+pub fn classify_http_error(err: &reqwest::Error) -> HttpErrorKind {
+    if err.is_connect() || err.is_timeout() {
+        return HttpErrorKind::Network;
+    }
+    match err.status() {
+        Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => HttpErrorKind::Auth,
+        Some(StatusCode::NOT_FOUND) => HttpErrorKind::NotFound,
+        Some(status) if status.is_server_error() => HttpErrorKind::ServerError,
+        _ => HttpErrorKind::Unknown,
+    }
+}
+
+/// Gateway errors usually mean the registry is restarting or overloaded, so the request is worth retrying.
+fn is_transient(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Walks a paginated endpoint, bumping `offset` by `page_size` until a page comes back short,
+/// and returns the items of every page.
+async fn fetch_all_pages<T: DeserializeOwned>(
+    client: &ClientV2,
+    url: Url,
+    auth: &context::Auth,
+    page_size: usize,
+) -> Result<Vec<T>, Error> {
+    if page_size == 0 {
+        return Err(Error::setup("The fetch page size must be greater than 0"));
+    }
+    let mut items = Vec::new();
+    loop {
+        let req = client
+            .client
+            .get(url.clone())
+            .header(header::ACCEPT, "application/json")
+            .query(&[("offset", items.len()), ("limit", page_size)]);
+        let req = client.with_context(req, auth);
+        tracing::debug!("{:?}", req);
+
+        let res = artifact_error_for_status(client.send(req).await?).await?;
+        let page: Page<T> = res.json().await?;
+        let len = page.items.len();
+        items.extend(page.items);
+        if len < page_size {
+            return Ok(items);
+        }
+    }
+}
+
+/// Like `reqwest::Response::error_for_status`, but includes the response body
+/// in the error for 4xx responses, as the registry usually explains the failure there.
+async fn error_for_status(res: Response) -> Result<Response, Error> {
+    let err = match res.error_for_status_ref() {
+        Ok(_) => return Ok(res),
+        Err(err) => err,
+    };
+    let body = if res.status().is_client_error() {
+        res.text().await.ok().filter(|body| !body.is_empty())
+    } else {
+        None
+    };
+    Err(Error::Http { err, body })
+}
+
+/// Like `error_for_status`, but tells missing artifacts and rejected credentials apart from other failures.
+async fn artifact_error_for_status(res: Response) -> Result<Response, Error> {
+    let url = res.url().to_string();
+    match res.status() {
+        StatusCode::NOT_FOUND => Err(Error::NotFound { url }),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(Error::Unauthorized { url }),
+        _ => error_for_status(res).await,
+    }
+}
+
+/// Applies the context credentials: a bearer token for OIDC and auth helper tokens,
+/// an `Authorization: Basic` header for basic auth and the `apicurio_registry_api_key`
+/// query parameter for API keys.
 fn with_auth(req: RequestBuilder, auth: &context::Auth) -> RequestBuilder {
     match auth {
         Auth::Oidc { access_token, .. } => req.bearer_auth(access_token),
         Auth::Basic { username, password } => req.basic_auth(username, password.as_ref()),
+        Auth::Bearer { token } => req.bearer_auth(token),
+        Auth::ApiKeyQuery { key } => req.query(&[("apicurio_registry_api_key", key)]),
         Auth::None => req,
     }
 }
@@ -264,6 +695,8 @@ pub struct ArtifactVersionMetadata {
     labels: Vec<String>,
     #[serde(default)]
     properties: HashMap<String, String>,
+    #[serde(default)]
+    state: ArtifactState,
 }
 
 #[allow(clippy::from_over_into)]
@@ -282,6 +715,7 @@ impl Into<provider::ArtifactVersionMetadata> for ArtifactVersionMetadata {
             content_id: self.content_id,
             labels: self.labels,
             properties: self.properties,
+            state: self.state,
         }
     }
 }
@@ -294,3 +728,243 @@ struct UpdateArtifactMetadataBody {
     labels: Option<Vec<String>>,
     properties: Option<HashMap<String, String>>,
 }
+
+#[derive(Debug, Serialize)]
+struct UpdateStateBody {
+    state: ArtifactState,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupMetadata {
+    id: String,
+    description: Option<String>,
+    created_by: String,
+    created_on: String,
+    modified_by: String,
+    modified_on: String,
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<provider::GroupMetadata> for GroupMetadata {
+    fn into(self) -> provider::GroupMetadata {
+        provider::GroupMetadata {
+            group_id: self.id,
+            description: self.description,
+            created_by: self.created_by,
+            created_on: self.created_on,
+            modified_by: self.modified_by,
+            modified_on: self.modified_on,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct CreateGroupBody {
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Reference {
+    name: String,
+    group_id: Option<String>,
+    artifact_id: String,
+    version: String,
+}
+
+impl From<Reference> for ArtifactReference {
+    fn from(reference: Reference) -> Self {
+        Self {
+            name: reference.name,
+            // Artifacts in the default group are referenced without one
+            group_id: reference.group_id.unwrap_or_else(|| "default".to_string()),
+            artifact_id: reference.artifact_id,
+            version: reference.version,
+        }
+    }
+}
+
+/// One page of a paginated search. Each endpoint names its result array after what it lists.
+#[derive(Debug, Deserialize)]
+struct Page<T> {
+    #[serde(alias = "versions", alias = "artifacts")]
+    items: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchedVersion {
+    version: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchedArtifact {
+    group_id: String,
+    id: String,
+    name: Option<String>,
+    description: Option<String>,
+    #[serde(rename = "type")]
+    artifact_type: ArtifactType,
+    created_by: String,
+    created_on: String,
+    modified_by: String,
+    modified_on: String,
+    #[serde(default)]
+    labels: Vec<String>,
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<provider::SearchedArtifact> for SearchedArtifact {
+    fn into(self) -> provider::SearchedArtifact {
+        provider::SearchedArtifact {
+            group_id: self.group_id,
+            id: self.id,
+            name: self.name,
+            description: self.description,
+            artifact_type: self.artifact_type,
+            created_by: self.created_by,
+            created_on: self.created_on,
+            modified_by: self.modified_by,
+            modified_on: self.modified_on,
+            labels: self.labels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use warp::Filter;
+
+    use super::*;
+    use crate::test_util::{self, system_info};
+
+    /// Serves `routes` on an ephemeral port and returns a client pointing to it.
+    fn serve<F>(routes: F) -> ClientV2
+    where
+        F: Filter + Clone + Send + Sync + 'static,
+        F::Extract: warp::Reply,
+    {
+        ClientV2::new(
+            test_util::serve(routes),
+            reqwest::Client::new(),
+            HashMap::new(),
+            0,
+            crate::client::DEFAULT_LARGE_ARTIFACT_THRESHOLD_BYTES,
+            crate::client::DEFAULT_FETCH_PAGE_SIZE,
+        )
+    }
+
+    #[tokio::test]
+    async fn basic_auth_sends_authorization_header() {
+        let client = serve(
+            warp::header::exact("authorization", "Basic Ym9iOnNlY3JldA==").and(system_info()),
+        );
+        let auth = Auth::Basic {
+            username: "bob".to_string(),
+            password: Some("secret".to_string()),
+        };
+        client.system_info(&auth).await.expect("system info");
+    }
+
+    #[tokio::test]
+    async fn basic_auth_without_password_sends_empty_password() {
+        let client =
+            serve(warp::header::exact("authorization", "Basic Ym9iOg==").and(system_info()));
+        let auth = Auth::Basic {
+            username: "bob".to_string(),
+            password: None,
+        };
+        client.system_info(&auth).await.expect("system info");
+    }
+
+    #[tokio::test]
+    async fn api_key_is_sent_as_query_parameter() {
+        let client = serve(
+            warp::query::<HashMap<String, String>>()
+                .and_then(|query: HashMap<String, String>| async move {
+                    match query.get("apicurio_registry_api_key") {
+                        Some(key) if key == "secret" => Ok(()),
+                        _ => Err(warp::reject()),
+                    }
+                })
+                .untuple_one()
+                .and(system_info()),
+        );
+        let auth = Auth::ApiKeyQuery {
+            key: "secret".to_string(),
+        };
+        client.system_info(&auth).await.expect("system info");
+    }
+
+    #[tokio::test]
+    async fn rate_limited_request_is_retried_without_retries() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let rate_limit = warp::any().and_then(move || {
+            let first = counter.fetch_add(1, Ordering::SeqCst) == 0;
+            async move {
+                if first {
+                    Ok(warp::reply::with_header(
+                        StatusCode::TOO_MANY_REQUESTS,
+                        "Retry-After",
+                        "0",
+                    ))
+                } else {
+                    Err(warp::reject())
+                }
+            }
+        });
+        let client = serve(rate_limit.or(system_info()));
+
+        client.system_info(&Auth::None).await.expect("system info");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn versions_are_fetched_page_by_page() {
+        let versions = warp::path!(
+            "apis" / "registry" / "v2" / "groups" / "g" / "artifacts" / "a" / "versions"
+        )
+        .and(warp::query::<HashMap<String, usize>>())
+        .map(|query: HashMap<String, usize>| {
+            let versions: Vec<_> = (1..=5)
+                .skip(query["offset"])
+                .take(query["limit"])
+                .map(|version| serde_json::json!({ "version": version.to_string() }))
+                .collect();
+            warp::reply::json(&serde_json::json!({ "versions": versions, "count": 5 }))
+        });
+        let client = crate::client::Client::builder(test_util::serve(versions))
+            .fetch_page_size(2)
+            .build()
+            .unwrap()
+            .v2();
+
+        let versions = client
+            .list_artifact_versions("g", "a", &Auth::None)
+            .await
+            .expect("list versions");
+        assert_eq!(versions, ["1", "2", "3", "4", "5"]);
+    }
+
+    #[tokio::test]
+    async fn zero_fetch_page_size_is_rejected() {
+        let client = crate::client::Client::builder(test_util::serve(system_info()))
+            .fetch_page_size(0)
+            .build()
+            .unwrap()
+            .v2();
+
+        let err = client
+            .list_artifact_versions("g", "a", &Auth::None)
+            .await
+            .expect_err("zero page size");
+        assert!(matches!(err, Error::Setup(_)), "{:?}", err);
+    }
+}
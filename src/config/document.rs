@@ -0,0 +1,116 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+
+use crate::config::Config;
+use crate::error::Error;
+
+/// Raw view of a config file, used to edit it in place without losing comments and formatting.
+///
+/// Edits are applied to the text directly rather than to a deserialized `Config`,
+/// so only the lines touched by an edit change.
+#[derive(Debug)]
+pub struct Document {
+    path: PathBuf,
+    content: String,
+}
+
+impl Document {
+    pub async fn load(path: PathBuf) -> Result<Self, Error> {
+        let content = tokio::fs::read_to_string(&path).await?;
+        Ok(Self { path, content })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends `entry` to the end of the top level `section` sequence, creating the section if missing.
+    pub fn append(&mut self, section: &str, entry: &impl Serialize) -> Result<(), Error> {
+        let mut lines: Vec<String> = self.content.lines().map(str::to_string).collect();
+        let header = lines
+            .iter()
+            .position(|line| is_section_header(line, section));
+
+        let (insert_at, indent) = match header {
+            Some(header) => {
+                let value = lines[header][section.len() + 1..].trim();
+                let value = value.split('#').next().unwrap_or_default().trim();
+                match value {
+                    "" => {}
+                    "[]" => lines[header] = format!("{}:", section),
+                    _ => {
+                        return Err(Error::setup(format!(
+                            "Cannot edit `{}` in {}: only block style sequences are supported",
+                            section,
+                            self.path.display()
+                        )))
+                    }
+                }
+                section_bounds(&lines, header)
+            }
+            None => {
+                if lines.last().map(|line| !line.trim().is_empty()) == Some(true) {
+                    lines.push(String::new());
+                }
+                lines.push(format!("{}:", section));
+                (lines.len(), "  ".to_string())
+            }
+        };
+
+        let entry = serde_yaml::to_string(entry)?;
+        let entry = entry.strip_prefix("---\n").unwrap_or(&entry);
+        let item = entry.lines().enumerate().map(|(i, line)| {
+            if i == 0 {
+                format!("{}- {}", indent, line)
+            } else {
+                format!("{}  {}", indent, line)
+            }
+        });
+        let tail = lines.split_off(insert_at);
+        lines.extend(item);
+        lines.extend(tail);
+
+        let mut content = lines.join("\n");
+        content.push('\n');
+        Config::parse(&content)?;
+        self.content = content;
+        Ok(())
+    }
+
+    pub async fn save(&self) -> Result<(), Error> {
+        let mut file = tokio::fs::File::create(&self.path).await?;
+        file.write_all(self.content.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+fn is_section_header(line: &str, section: &str) -> bool {
+    line.strip_prefix(section)
+        .and_then(|rest| rest.strip_prefix(':'))
+        .map(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+        .unwrap_or(false)
+}
+
+/// Returns the line right after the last entry of the section starting at `header`,
+/// and the indentation used by its sequence items.
+fn section_bounds(lines: &[String], header: usize) -> (usize, String) {
+    let mut end = header + 1;
+    let mut indent = None;
+    for (i, line) in lines.iter().enumerate().skip(header + 1) {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let is_nested = line.starts_with(char::is_whitespace) || trimmed.starts_with("- ");
+        if !is_nested {
+            break;
+        }
+        if indent.is_none() && trimmed.starts_with("- ") {
+            indent = Some(line[..line.len() - trimmed.len()].to_string());
+        }
+        end = i + 1;
+    }
+    (end, indent.unwrap_or_else(|| "  ".to_string()))
+}
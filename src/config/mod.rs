@@ -0,0 +1,815 @@
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+use crate::error::Error;
+use crate::provider::ArtifactType;
+
+pub mod document;
+
+/// Config written by `init --template full`, documenting every supported field.
+const FULL_TEMPLATE: &str = r#"# apicurio-sync configuration file.
+# See https://www.apicur.io/registry/docs/ for the Apicurio Registry documentation.
+
+# Artifacts uploaded to the registry on `sync`.
+# Pushes use the registry `RETURN_OR_UPDATE` ifExists strategy: pushing unchanged content
+# returns the existing version, while changed content creates a new version of the artifact.
+push: []
+#  - group: my-group
+#    artifact: my-artifact
+#    path: schemas/my-artifact.proto
+#    # Instead of path, content can be fetched from a URL with a plain unauthenticated GET
+#    # url: https://cdn.example.com/schemas/my-artifact.proto
+#    # Optional, the registry detects the type from the content when absent.
+#    # One of AVRO, PROTOBUF, JSON, KCONNECT, OPENAPI, ASYNCAPI, GRAPHQL, WSDL, XSD
+#    type: PROTOBUF
+#    name: My artifact
+#    description: What this artifact is about
+#    labels:
+#      - example
+#    properties:
+#      example.com/owner: my-team
+#    # Optional, names every new version after the push time, e.g. UTC_20211001120000
+#    bump_version: false
+#    # Optional, push artifacts to push before this one, e.g. the ones it references
+#    depends_on:
+#      - schemas/common.proto
+#    # Optional, a command run with `sh -c` in the working directory before the artifact is read,
+#    # with ARTIFACT_PATH, ARTIFACT_GROUP and ARTIFACT_ID set. The artifact is not pushed if it fails
+#    on_push: buf format -w "$ARTIFACT_PATH"
+#    # Optional, update (the default) creates a new version when the content changed, while skip
+#    # only pushes the artifact if it does not exist in the registry yet
+#    on_conflict: update
+#
+# Repeated values can be shared with YAML anchors and aliases, and whole entries with merge keys:
+#  - &events
+#    group: events
+#    artifact: order-created
+#    path: schemas/order-created.avsc
+#    labels: &event-labels
+#      - events
+#  - <<: *events
+#    artifact: order-shipped
+#    path: schemas/order-shipped.avsc
+
+# Artifacts downloaded from the registry on `sync`.
+pull: []
+#  - group: my-group
+#    artifact: other-artifact
+#    path: schemas/other-artifact.proto
+#    # Optional, defaults to the latest version at the time of the first sync.
+#    # The resolved version is recorded in the lockfile.
+#    version: "1"
+#    # Alternatively, a SemVer range resolved to the highest matching version on `update`
+#    # version_expression: ^1.2
+#    # Optional, saves the artifact as schemas/other.proto instead
+#    rename: other.proto
+#    # Optional, one of raw (the default), pretty_json or pretty_yaml
+#    output_format: raw
+#    # Optional, also pulls the artifacts this one references (e.g. imported Protobuf files)
+#    # next to it, under the names it references them by
+#    pull_references: false
+#    # Optional, a command run with `sh -c` in the working directory after the artifact is written,
+#    # with ARTIFACT_PATH, ARTIFACT_GROUP, ARTIFACT_ID and ARTIFACT_VERSION set. Failing fails the sync
+#    on_pull: protoc --go_out=gen "$ARTIFACT_PATH"
+#    # Optional, removes or adds a prefix to the file name the artifact is written to,
+#    # e.g. saving schemas/com.example.User.avsc as schemas/User.avsc
+#    strip_prefix: com.example.
+#    add_prefix: v1-
+
+# Optional defaults for every push artifact. Labels are added to the ones of each artifact,
+# properties are added unless an artifact sets them, and the others apply when an artifact leaves them unset.
+#global:
+#  labels:
+#    - my-team
+#  properties:
+#    example.com/owner: my-team
+#  type: PROTOBUF
+#  on_conflict: update
+
+# Optional URLs notified with a JSON summary after every `sync` and `update`.
+#on_success_webhook: https://example.com/hooks/success
+#on_failure_webhook: https://example.com/hooks/failure
+
+# Optional artifacts added to the ones above, or replacing them, when running with `--profile <name>`.
+#profiles:
+#  staging:
+#    pull:
+#      - group: my-group
+#        artifact: staging-artifact
+#        path: schemas/staging-artifact.proto
+"#;
+
+/// JSON Schema of the config file, checked by `config validate --strict`.
+///
+/// Unlike deserialization, which ignores unknown fields, it rejects them so that typos are caught.
+/// Top level keys starting with `x-` are allowed, to hold YAML anchors.
+const CONFIG_SCHEMA: &str = r##"{
+  "$schema": "http://json-schema.org/draft-07/schema#",
+  "type": "object",
+  "properties": {
+    "push": { "type": "array", "items": { "$ref": "#/definitions/push" } },
+    "pull": { "type": "array", "items": { "$ref": "#/definitions/pull" } },
+    "on_success_webhook": { "type": "string" },
+    "on_failure_webhook": { "type": "string" },
+    "global": {
+      "type": "object",
+      "properties": {
+        "labels": { "type": "array", "items": { "type": "string" } },
+        "properties": { "type": "object", "additionalProperties": { "type": "string" } },
+        "type": { "$ref": "#/definitions/artifact_type" },
+        "on_conflict": { "$ref": "#/definitions/on_conflict" }
+      },
+      "additionalProperties": false
+    },
+    "profiles": {
+      "type": "object",
+      "additionalProperties": {
+        "type": "object",
+        "properties": {
+          "push": { "type": "array", "items": { "$ref": "#/definitions/push" } },
+          "pull": { "type": "array", "items": { "$ref": "#/definitions/pull" } }
+        },
+        "additionalProperties": false
+      }
+    }
+  },
+  "patternProperties": { "^x-": {} },
+  "additionalProperties": false,
+  "definitions": {
+    "artifact_type": {
+      "enum": ["AVRO", "PROTOBUF", "JSON", "KCONNECT", "OPENAPI", "ASYNCAPI", "GRAPHQL", "WSDL", "XSD"]
+    },
+    "on_conflict": { "enum": ["update", "skip"] },
+    "push": {
+      "type": "object",
+      "required": ["group", "artifact"],
+      "properties": {
+        "group": { "type": "string" },
+        "artifact": { "type": "string" },
+        "path": { "type": "string" },
+        "url": { "type": "string" },
+        "type": { "$ref": "#/definitions/artifact_type" },
+        "name": { "type": "string" },
+        "description": { "type": "string" },
+        "labels": { "type": "array", "items": { "type": "string" } },
+        "properties": { "type": "object", "additionalProperties": { "type": "string" } },
+        "bump_version": { "type": "boolean" },
+        "depends_on": { "type": "array", "items": { "type": "string" } },
+        "on_push": { "type": "string" },
+        "on_conflict": { "$ref": "#/definitions/on_conflict" }
+      },
+      "additionalProperties": false
+    },
+    "pull": {
+      "type": "object",
+      "required": ["group", "artifact", "path"],
+      "properties": {
+        "group": { "type": "string" },
+        "artifact": { "type": "string" },
+        "path": { "type": "string" },
+        "version": { "type": ["string", "integer"] },
+        "version_expression": { "type": "string" },
+        "rename": { "type": "string" },
+        "output_format": { "enum": ["raw", "pretty_json", "pretty_yaml"] },
+        "pull_references": { "type": "boolean" },
+        "on_pull": { "type": "string" },
+        "strip_prefix": { "type": "string" },
+        "add_prefix": { "type": "string" }
+      },
+      "additionalProperties": false
+    }
+  }
+}"##;
+
+/// What `init` writes into a new config file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Template {
+    Minimal,
+    Full,
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "minimal" => Ok(Template::Minimal),
+            "full" => Ok(Template::Full),
+            _ => Err(format!(
+                "unknown template `{}`, expected `minimal` or `full`",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Config {
+    #[serde(default)]
+    pub push: Vec<PushArtifactRef>,
+    #[serde(default)]
+    pub pull: Vec<PullArtifactRef>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_success_webhook: Option<Url>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_failure_webhook: Option<Url>,
+    /// Artifacts added or replaced when running with `--profile`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub profiles: HashMap<String, ProfileOverride>,
+    /// Defaults for fields that push artifacts leave unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub global: Option<GlobalConfig>,
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct GlobalConfig {
+    /// Added to the labels of every push artifact.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub labels: Option<Vec<String>>,
+    /// Added to the properties of every push artifact that does not set them itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub properties: Option<HashMap<String, String>>,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub artifact_type: Option<ArtifactType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_conflict: Option<OnConflict>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ProfileOverride {
+    #[serde(default)]
+    pub push: Vec<PushArtifactRef>,
+    #[serde(default)]
+    pub pull: Vec<PullArtifactRef>,
+}
+
+impl Config {
+    /// Loads the config at `path`, merging the artifacts of `profile` into the base ones if set.
+    pub async fn load_from_file(path: PathBuf, profile: Option<&str>) -> Result<Self, Error> {
+        let content = tokio::fs::read_to_string(&path).await?;
+        // serde_yaml errors already carry the line and column of the problem
+        let mut cfg_yaml = Config::parse(&content).map_err(|err| {
+            Error::setup(format!(
+                "Failed to parse config file {}: {}",
+                path.display(),
+                err
+            ))
+        })?;
+        cfg_yaml.path = path;
+        match profile {
+            Some(profile) => cfg_yaml.with_profile(profile),
+            None => Ok(cfg_yaml),
+        }
+    }
+
+    /// Parses a config document. serde_yaml resolves anchors and aliases, but not `<<` merge keys,
+    /// so documents using them are expanded first, at the cost of error positions.
+    pub fn parse(content: &str) -> Result<Self, serde_yaml::Error> {
+        if !content.contains("<<") {
+            return serde_yaml::from_str(content);
+        }
+        let mut value: serde_yaml::Value = serde_yaml::from_str(content)?;
+        expand_merge_keys(&mut value);
+        serde_yaml::from_value(value)
+    }
+
+    /// Checks the config file at `path` against `CONFIG_SCHEMA`, listing every violation
+    /// with the location of the offending value.
+    pub async fn validate_schema(path: &Path) -> Result<(), Error> {
+        let content = tokio::fs::read_to_string(path).await?;
+        let mut value: serde_yaml::Value = serde_yaml::from_str(&content).map_err(|err| {
+            Error::setup(format!(
+                "Failed to parse config file {}: {}",
+                path.display(),
+                err
+            ))
+        })?;
+        expand_merge_keys(&mut value);
+        let instance = serde_json::to_value(value)?;
+        let schema = serde_json::from_str(CONFIG_SCHEMA).expect("valid config schema JSON");
+        let schema = jsonschema::JSONSchema::compile(&schema).expect("valid config schema");
+        let result = schema.validate(&instance);
+        if let Err(errors) = result {
+            let problems: Vec<String> = errors
+                .map(|err| match err.instance_path.to_string() {
+                    location if location.is_empty() => err.to_string(),
+                    location => format!("{}: {}", location, err),
+                })
+                .collect();
+            return Err(Error::setup(format!(
+                "Config file {} does not match the schema:\n  {}",
+                path.display(),
+                problems.join("\n  ")
+            )));
+        }
+        Ok(())
+    }
+
+    fn with_profile(mut self, profile: &str) -> Result<Self, Error> {
+        let overrides = self.profiles.remove(profile).ok_or_else(|| {
+            Error::setup(format!(
+                "Profile {} not found in config file {}",
+                profile,
+                self.path.display()
+            ))
+        })?;
+        let overlay = Config {
+            push: overrides.push,
+            pull: overrides.pull,
+            ..Default::default()
+        };
+        Ok(Config::merge(self, overlay))
+    }
+
+    pub async fn write_empty(
+        path: PathBuf,
+        force: bool,
+        template: Template,
+    ) -> Result<Self, Error> {
+        let mut options = OpenOptions::new();
+        options.write(true);
+        if force {
+            if tokio::fs::metadata(&path).await.is_ok() {
+                tracing::warn!("Overwriting existing config file {}", path.display());
+            }
+            options.truncate(true).create(true);
+        } else {
+            options.create_new(true);
+        }
+        let mut file = match options.open(&path).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                return Err(Error::setup(format!(
+                    "Config file {} already exists. Use --force to overwrite it",
+                    path.display()
+                )))
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let cfg = Config {
+            path,
+            ..Default::default()
+        };
+        let content = match template {
+            Template::Minimal => serde_yaml::to_vec(&cfg)?,
+            Template::Full => FULL_TEMPLATE.as_bytes().to_vec(),
+        };
+        file.write_all(&content).await?;
+        Ok(cfg)
+    }
+
+    /// Checks the config for mistakes that would silently corrupt data on sync:
+    /// the same push artifact declared twice and the same file being both pushed and pulled.
+    /// Unless `allow_outside_paths` is set, every path must also be relative and stay inside
+    /// the working directory, so the config stays portable.
+    pub fn validate(&self, allow_outside_paths: bool) -> Result<(), Error> {
+        let mut problems = Vec::new();
+
+        if !allow_outside_paths {
+            let paths = self
+                .push
+                .iter()
+                .filter_map(|a| a.path.as_ref())
+                .chain(self.pull.iter().map(|a| &a.path));
+            for path in paths {
+                if path.is_absolute() {
+                    problems.push(format!("path {} is absolute", path.display()));
+                } else if escapes_workdir(path) {
+                    problems.push(format!(
+                        "path {} is outside the working directory",
+                        path.display()
+                    ));
+                }
+            }
+        }
+
+        let mut coordinates = HashSet::new();
+        for artifact in &self.push {
+            if artifact.path.is_some() == artifact.url.is_some() {
+                problems.push(format!(
+                    "push artifact {}/{} must set exactly one of `path` and `url`",
+                    artifact.group, artifact.artifact
+                ));
+            }
+            if !coordinates.insert((&artifact.group, &artifact.artifact)) {
+                problems.push(format!(
+                    "push artifact {}/{} is declared more than once",
+                    artifact.group, artifact.artifact
+                ));
+            }
+        }
+
+        for artifact in &self.pull {
+            if artifact.version.is_some() && artifact.version_expression.is_some() {
+                problems.push(format!(
+                    "pull artifact {}/{} must set at most one of `version` and `version_expression`",
+                    artifact.group, artifact.artifact
+                ));
+            }
+            if let Some(rename) = &artifact.rename {
+                if matches!(rename.as_str(), "" | "." | "..")
+                    || rename.contains(std::path::is_separator)
+                {
+                    problems.push(format!(
+                        "pull artifact {}/{} rename {:?} must be a plain file name",
+                        artifact.group, artifact.artifact, rename
+                    ));
+                }
+            }
+        }
+
+        let sources: HashSet<PathBuf> = self.push.iter().map(PushArtifactRef::source).collect();
+        for artifact in &self.push {
+            for dependency in &artifact.depends_on {
+                if !sources.contains(dependency) {
+                    problems.push(format!(
+                        "push artifact {}/{} depends on {}, which is not a push artifact",
+                        artifact.group,
+                        artifact.artifact,
+                        dependency.display()
+                    ));
+                }
+            }
+        }
+
+        let push_paths: HashSet<&Path> =
+            self.push.iter().filter_map(|a| a.path.as_deref()).collect();
+        for artifact in &self.pull {
+            if push_paths.contains(artifact.path.as_path()) {
+                problems.push(format!(
+                    "path {} is used by both a push and a pull artifact",
+                    artifact.path.display()
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::setup(format!(
+                "Invalid config file {}:\n  {}",
+                self.path.display(),
+                problems.join("\n  ")
+            )))
+        }
+    }
+
+    /// Combines two configs, with `overlay` artifacts replacing `base` ones with the same
+    /// `(group, artifact, path)`. The resulting config keeps the path of `base`.
+    pub fn merge(base: Config, overlay: Config) -> Config {
+        let mut push = base.push;
+        for artifact in overlay.push {
+            match push
+                .iter_mut()
+                .find(|existing| existing.key() == artifact.key())
+            {
+                Some(existing) => *existing = artifact,
+                None => push.push(artifact),
+            }
+        }
+
+        let mut pull = base.pull;
+        for artifact in overlay.pull {
+            match pull
+                .iter_mut()
+                .find(|existing| existing.key() == artifact.key())
+            {
+                Some(existing) => *existing = artifact,
+                None => pull.push(artifact),
+            }
+        }
+
+        Config {
+            push,
+            pull,
+            on_success_webhook: overlay.on_success_webhook.or(base.on_success_webhook),
+            on_failure_webhook: overlay.on_failure_webhook.or(base.on_failure_webhook),
+            profiles: base.profiles,
+            global: overlay.global.or(base.global),
+            path: base.path,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            push: Vec::new(),
+            pull: Vec::new(),
+            on_success_webhook: None,
+            on_failure_webhook: None,
+            profiles: HashMap::new(),
+            global: None,
+            path: PathBuf::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PushArtifactRef {
+    pub group: String,
+    pub artifact: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// Remote content to push instead of a local file, fetched with a plain unauthenticated GET.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<Url>,
+    #[serde(rename = "type")]
+    pub artifact_type: Option<ArtifactType>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub labels: Option<Vec<String>>,
+    pub properties: Option<HashMap<String, String>>,
+    /// Name every pushed version after the push time, e.g. `UTC_20211001120000`.
+    #[serde(default)]
+    pub bump_version: bool,
+    /// Paths of other push artifacts that must be pushed first, e.g. the ones this one references.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<PathBuf>,
+    /// Shell command run before the artifact is read, e.g. to lint or format it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_push: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_conflict: Option<OnConflict>,
+}
+
+/// What to do when pushing an artifact that already exists in the registry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnConflict {
+    /// Create a new version if the content changed.
+    #[default]
+    Update,
+    /// Leave the artifact in the registry as it is.
+    Skip,
+}
+
+impl PushArtifactRef {
+    /// Fills the fields left unset with the `global` defaults. Labels are combined,
+    /// and properties set on the artifact win over the global ones with the same name.
+    pub fn with_global_defaults(mut self, global: &GlobalConfig) -> Self {
+        if let Some(labels) = &global.labels {
+            let own = self.labels.take().unwrap_or_default();
+            let mut combined = labels.clone();
+            combined.extend(own.into_iter().filter(|label| !labels.contains(label)));
+            self.labels = Some(combined);
+        }
+        if let Some(properties) = &global.properties {
+            let mut combined = properties.clone();
+            combined.extend(self.properties.take().unwrap_or_default());
+            self.properties = Some(combined);
+        }
+        if self.artifact_type.is_none() {
+            self.artifact_type = global.artifact_type.clone();
+        }
+        if self.on_conflict.is_none() {
+            self.on_conflict = global.on_conflict;
+        }
+        self
+    }
+
+    /// Identifies the artifact content in the plan and the lockfile: its local path,
+    /// or its URL for remote content.
+    pub fn source(&self) -> PathBuf {
+        match (&self.path, &self.url) {
+            (Some(path), _) => path.clone(),
+            (None, Some(url)) => PathBuf::from(url.as_str()),
+            (None, None) => PathBuf::new(),
+        }
+    }
+
+    fn key(&self) -> (&str, &str, PathBuf) {
+        (&self.group, &self.artifact, self.source())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PullArtifactRef {
+    pub group: String,
+    pub artifact: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// SemVer range like `^1.2`, resolved to the highest matching version on `update`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version_expression: Option<String>,
+    pub path: PathBuf,
+    /// File name to save the artifact as, in the parent directory of `path`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rename: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_format: Option<OutputFormat>,
+    /// Also pull the artifacts referenced by this one, next to it, under the names it references them by.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pull_references: bool,
+    /// Shell command run after the artifact is written, e.g. to generate code from it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_pull: Option<String>,
+    /// Removed from the start of the file name the artifact is written to, if present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strip_prefix: Option<String>,
+    /// Added to the start of the file name the artifact is written to, after `strip_prefix`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub add_prefix: Option<String>,
+}
+
+/// How pulled content is reformatted before being written to disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// Exactly as stored in the registry.
+    #[default]
+    Raw,
+    PrettyJson,
+    PrettyYaml,
+}
+
+impl PullArtifactRef {
+    fn key(&self) -> (&str, &str, &Path) {
+        (&self.group, &self.artifact, &self.path)
+    }
+}
+
+/// Replaces `<<` merge keys with the entries of the mappings they reference, without overriding
+/// the entries already present. With a sequence of mappings, the first ones take precedence.
+fn expand_merge_keys(value: &mut serde_yaml::Value) {
+    match value {
+        serde_yaml::Value::Mapping(mapping) => {
+            let merged = mapping.remove(&serde_yaml::Value::String("<<".to_string()));
+            let sources = match merged {
+                Some(serde_yaml::Value::Sequence(sources)) => sources,
+                Some(source) => vec![source],
+                None => Vec::new(),
+            };
+            for mut source in sources {
+                expand_merge_keys(&mut source);
+                if let serde_yaml::Value::Mapping(source) = source {
+                    for (key, value) in source {
+                        if !mapping.contains_key(&key) {
+                            mapping.insert(key, value);
+                        }
+                    }
+                }
+            }
+            for (_, value) in mapping.iter_mut() {
+                expand_merge_keys(value);
+            }
+        }
+        serde_yaml::Value::Sequence(values) => values.iter_mut().for_each(expand_merge_keys),
+        _ => {}
+    }
+}
+
+fn escapes_workdir(path: &Path) -> bool {
+    let mut depth = 0usize;
+    for component in path.components() {
+        match component {
+            Component::ParentDir if depth == 0 => return true,
+            Component::ParentDir => depth -= 1,
+            Component::Normal(_) => depth += 1,
+            _ => {}
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MERGE_KEYS: &str = r#"
+x-defaults: &defaults
+  group: events
+  labels:
+    - events
+  properties: &properties
+    owner: team-a
+    tier: gold
+x-other: &other
+  group: other
+  description: other defaults
+push:
+  - <<: *defaults
+    artifact: order-created
+    path: order-created.avsc
+  - <<: *defaults
+    artifact: order-shipped
+    path: order-shipped.avsc
+    labels:
+      - shipping
+    properties:
+      <<: *properties
+      tier: silver
+  - <<: [*defaults, *other]
+    artifact: order-cancelled
+    path: order-cancelled.avsc
+"#;
+
+    fn properties(entries: &[(&str, &str)]) -> Option<HashMap<String, String>> {
+        Some(
+            entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn merge_keys_expand_into_push_artifacts() {
+        let config = Config::parse(MERGE_KEYS).unwrap();
+        let created = &config.push[0];
+        assert_eq!(created.group, "events");
+        assert_eq!(created.artifact, "order-created");
+        assert_eq!(created.labels, Some(vec!["events".to_string()]));
+        assert_eq!(
+            created.properties,
+            properties(&[("owner", "team-a"), ("tier", "gold")])
+        );
+    }
+
+    #[test]
+    fn merge_keys_do_not_override_local_keys() {
+        let config = Config::parse(MERGE_KEYS).unwrap();
+        let shipped = &config.push[1];
+        assert_eq!(shipped.group, "events");
+        assert_eq!(shipped.labels, Some(vec!["shipping".to_string()]));
+        assert_eq!(
+            shipped.properties,
+            properties(&[("owner", "team-a"), ("tier", "silver")])
+        );
+    }
+
+    #[test]
+    fn first_merged_mapping_takes_precedence() {
+        let config = Config::parse(MERGE_KEYS).unwrap();
+        let cancelled = &config.push[2];
+        assert_eq!(cancelled.group, "events");
+        assert_eq!(cancelled.description.as_deref(), Some("other defaults"));
+        assert_eq!(cancelled.labels, Some(vec!["events".to_string()]));
+    }
+
+    const ANCHORS: &str = r#"
+push:
+  - group: &group events
+    artifact: order-created
+    path: order-created.avsc
+    labels: &labels
+      - events
+      - orders
+    properties: &properties
+      owner: team-a
+  - group: *group
+    artifact: order-shipped
+    path: order-shipped.avsc
+    labels: *labels
+    properties: *properties
+"#;
+
+    #[test]
+    fn aliases_expand_to_their_anchored_values() {
+        let config = Config::parse(ANCHORS).unwrap();
+        let shipped = &config.push[1];
+        assert_eq!(shipped.group, "events");
+        assert_eq!(
+            shipped.labels,
+            Some(vec!["events".to_string(), "orders".to_string()])
+        );
+        assert_eq!(shipped.properties, properties(&[("owner", "team-a")]));
+    }
+
+    #[test]
+    fn expanded_aliases_survive_a_roundtrip() {
+        let config = Config::parse(ANCHORS).unwrap();
+        let written = serde_yaml::to_string(&config).unwrap();
+        let reparsed = Config::parse(&written).unwrap();
+        for (original, reparsed) in config.push.iter().zip(&reparsed.push) {
+            assert_eq!(reparsed.group, original.group);
+            assert_eq!(reparsed.labels, original.labels);
+            assert_eq!(reparsed.properties, original.properties);
+        }
+    }
+
+    #[test]
+    fn full_template_anchor_example_parses() {
+        let example: String = FULL_TEMPLATE
+            .lines()
+            .skip_while(|line| !line.starts_with("# Repeated values"))
+            .skip(1)
+            .take_while(|line| !line.is_empty())
+            .map(|line| format!("{}\n", line.trim_start_matches('#')))
+            .collect();
+        let config = Config::parse(&format!("push:\n{}", example)).unwrap();
+        let shipped = &config.push[1];
+        assert_eq!(shipped.group, "events");
+        assert_eq!(shipped.artifact, "order-shipped");
+        assert_eq!(shipped.labels, Some(vec!["events".to_string()]));
+    }
+}
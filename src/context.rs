@@ -1,30 +1,78 @@
 use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
 use std::io::ErrorKind;
 use std::path::Path;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use tokio::fs::{File, OpenOptions};
 use url::Url;
 
+use crate::auth::oidc::refresh_tokens;
 use crate::error::Error;
 
 const CONTEXT_NAME_ENVAR: &str = "APICURIO_SYNC_CONTEXT_NAME";
 const REGISTRY_URL_ENVAR: &str = "APICURIO_SYNC_REGISTRY_URL";
+/// Refresh OIDC tokens a bit before they expire, so they stay valid for the whole run.
+const OIDC_EXPIRY_MARGIN_SECS: i64 = 30;
 
 #[derive(Debug, Clone)]
 pub struct Context {
     pub context_name: String,
     pub registry_url: Url,
     pub auth: Auth,
+    pub auth_helper: Option<String>,
+    pub extra_headers: HashMap<String, String>,
 }
 
 impl Context {
     pub async fn try_new(file: &Path, context_name: Option<String>) -> Result<Self, Error> {
-        let file_ctx = Self::from_file(file, context_name).await?;
+        let file_ctx = Self::from_file_or_default(file, context_name).await?;
         let env_ctx = Self::from_env().await?;
         Self::merge(file_ctx, env_ctx)
-            .ok_or_else(|| Error::setup("Failed to read context from either file or env"))
+            .ok_or_else(|| Error::setup("Failed to read context from either file or env"))?
+            .resolve_auth(file)
+            .await
+    }
+
+    /// Like `try_new`, but never fails: also returns why each source did not provide a context,
+    /// so that users can tell which one is misconfigured.
+    pub async fn try_new_verbose(
+        file: &Path,
+        context_name: Option<String>,
+    ) -> (Option<Self>, Vec<String>) {
+        let mut reasons = Vec::new();
+        let file_ctx = match Self::from_file_or_default(file, context_name.clone()).await {
+            Ok(Some(ctx)) => Some(ctx),
+            Ok(None) if tokio::fs::metadata(file).await.is_err() => {
+                reasons.push(format!("context file {} does not exist", file.display()));
+                None
+            }
+            Ok(None) => {
+                reasons.push(match context_name {
+                    Some(name) => format!("context {} not found in {}", name, file.display()),
+                    None => format!("no current context set in {}", file.display()),
+                });
+                None
+            }
+            Err(err) => {
+                reasons.push(err.to_string());
+                None
+            }
+        };
+        let env_ctx = match Self::from_env().await {
+            Ok(Some(ctx)) => Some(ctx),
+            Ok(None) => {
+                reasons.push(format!("{} is not set", REGISTRY_URL_ENVAR));
+                None
+            }
+            Err(err) => {
+                reasons.push(format!("invalid {}: {}", REGISTRY_URL_ENVAR, err));
+                None
+            }
+        };
+        (Self::merge(file_ctx, env_ctx), reasons)
     }
 
     pub async fn from_file(
@@ -41,20 +89,37 @@ impl Context {
             }
         };
 
-        let content: ContextFile = serde_json::from_reader(file.into_std().await)?;
-        if let Some((name, RegistryContext { url, auth })) = context_name
+        let content = Self::parse_file(path, file).await?;
+        Ok(context_name
             .or_else(|| content.current_context.clone())
-            .as_ref()
-            .and_then(|name| content.contexts.get(name).map(|ctx| (name, ctx)))
-        {
-            Ok(Some(Context::new_with_auth(
-                name.clone(),
-                url.clone(),
-                auth.clone(),
-            )))
-        } else {
-            Ok(None)
+            .and_then(|name| {
+                let registry = content.contexts.get(&name)?.clone();
+                Some(Self::from_registry(name, registry))
+            }))
+    }
+
+    /// Like `from_file`, but falls back to the default registry URL of the file, if any,
+    /// when no context is requested and there is no current one.
+    async fn from_file_or_default(
+        path: &Path,
+        context_name: Option<String>,
+    ) -> Result<Option<Self>, Error> {
+        let requested = context_name.is_some();
+        let ctx = Self::from_file(path, context_name).await?;
+        if ctx.is_some() || requested {
+            return Ok(ctx);
         }
+        let content = match Self::read_file(path).await {
+            Ok(content) => content,
+            Err(Error::Io(err)) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        if content.current_context.is_some() {
+            return Ok(None);
+        }
+        Ok(content
+            .default_registry_url
+            .map(|url| Context::new(url.to_string(), url)))
     }
 
     pub async fn from_env() -> Result<Option<Self>, Error> {
@@ -79,6 +144,16 @@ impl Context {
             context_name,
             registry_url,
             auth,
+            auth_helper: None,
+            extra_headers: HashMap::new(),
+        }
+    }
+
+    fn from_registry(context_name: String, registry: RegistryContext) -> Self {
+        Self {
+            auth_helper: registry.auth_helper,
+            extra_headers: registry.extra_headers,
+            ..Self::new_with_auth(context_name, registry.url, registry.auth)
         }
     }
 
@@ -95,8 +170,19 @@ impl Context {
         }
     }
 
-    pub async fn write_empty_file(path: &Path) -> Result<(), Error> {
-        Self::write_file(&ContextFile::default(), path, false).await
+    pub async fn write_empty_file(path: &Path, force: bool) -> Result<(), Error> {
+        if force && tokio::fs::metadata(path).await.is_ok() {
+            tracing::warn!("Overwriting existing context file {}", path.display());
+        }
+        match Self::write_file(&ContextFile::default(), path, force).await {
+            Err(Error::Io(err)) if err.kind() == ErrorKind::AlreadyExists => {
+                Err(Error::setup(format!(
+                    "Context file {} already exists. Use --force to overwrite it",
+                    path.display()
+                )))
+            }
+            res => res,
+        }
     }
 
     pub async fn write(&self, path: &Path, current: bool) -> Result<(), Error> {
@@ -107,10 +193,14 @@ impl Context {
             .and_modify(|registry| {
                 registry.url = self.registry_url.clone();
                 registry.auth = self.auth.clone();
+                registry.auth_helper = self.auth_helper.clone();
+                registry.extra_headers = self.extra_headers.clone();
             })
             .or_insert_with(|| RegistryContext {
                 url: self.registry_url.clone(),
                 auth: self.auth.clone(),
+                auth_helper: self.auth_helper.clone(),
+                extra_headers: self.extra_headers.clone(),
             });
 
         if current {
@@ -120,13 +210,201 @@ impl Context {
         Self::write_file(&context_file, path, true).await
     }
 
+    /// Like `write`, but leaves the credentials out, for project local context files meant to be
+    /// checked into the repository. Credentials already in the entry are removed.
+    pub async fn write_without_credentials(&self, path: &Path, current: bool) -> Result<(), Error> {
+        let mut ctx = self.clone();
+        ctx.auth = Auth::None;
+        ctx.auth_helper = None;
+        ctx.write(path, current).await
+    }
+
+    /// Writes only the credentials of the context to the entry with the same name in `path`,
+    /// creating it with the context URL if missing. Used to keep the credentials of project local
+    /// contexts in the global context file.
+    pub async fn write_credentials(&self, path: &Path) -> Result<(), Error> {
+        let mut context_file = match Self::read_file(path).await {
+            Ok(content) => content,
+            Err(Error::Io(err)) if err.kind() == ErrorKind::NotFound => ContextFile::default(),
+            Err(err) => return Err(err),
+        };
+        context_file
+            .contexts
+            .entry(self.context_name.clone())
+            .and_modify(|registry| {
+                registry.auth = self.auth.clone();
+                registry.auth_helper = self.auth_helper.clone();
+            })
+            .or_insert_with(|| RegistryContext {
+                url: self.registry_url.clone(),
+                auth: self.auth.clone(),
+                auth_helper: self.auth_helper.clone(),
+                extra_headers: HashMap::new(),
+            });
+        Self::write_file(&context_file, path, true).await
+    }
+
+    /// Updates only the `auth` of the entry with the same name in `path`, leaving its URL, headers
+    /// and auth helper as they are on disk. Returns whether there was such an entry to update.
+    async fn write_auth(&self, path: &Path) -> Result<bool, Error> {
+        let mut context_file = Self::read_file(path).await?;
+        match context_file.contexts.get_mut(&self.context_name) {
+            Some(registry) => registry.auth = self.auth.clone(),
+            None => return Ok(false),
+        }
+        Self::write_file(&context_file, path, true).await?;
+        Ok(true)
+    }
+
+    pub async fn write_default_url(path: &Path, url: Url) -> Result<(), Error> {
+        let mut context_file = Self::read_file(path).await?;
+        context_file.default_registry_url = Some(url);
+        Self::write_file(&context_file, path, true).await
+    }
+
+    /// Returns every context in the file, in the order they were added, along with the name of the current one.
+    pub async fn list_contexts(path: &Path) -> Result<(Vec<Self>, Option<String>), Error> {
+        let content = match Self::read_file(path).await {
+            Ok(content) => content,
+            Err(Error::Io(err)) if err.kind() == ErrorKind::NotFound => ContextFile::default(),
+            Err(err) => return Err(err),
+        };
+        let contexts: Vec<Self> = content
+            .contexts
+            .into_iter()
+            .map(|(name, registry)| Self::from_registry(name, registry))
+            .collect();
+        Ok((contexts, content.current_context))
+    }
+
     pub fn set_auth(&mut self, auth: Auth) {
         self.auth = auth;
     }
 
+    /// Moves the context secrets to the system keychain, leaving references in their place.
+    pub fn store_secrets(&mut self) {
+        self.auth = self.auth.clone().store_secrets(&self.context_name);
+    }
+
+    /// Replaces keychain references in the context secrets with the values they point to.
+    pub fn resolve_secrets(mut self) -> Result<Self, Error> {
+        self.auth = self.auth.resolve_secrets()?;
+        Ok(self)
+    }
+
+    /// Checks that the credentials are usable before any request is made with them, refreshing
+    /// expired OIDC tokens. Refreshed tokens are saved back to `ctx_path`, as providers may
+    /// invalidate the previous refresh token.
+    pub async fn resolve_auth(mut self, ctx_path: &Path) -> Result<Self, Error> {
+        let name = self.context_name.clone();
+        let invalid = |kind: &str, reason: String| {
+            Error::Auth(
+                format!(
+                    "Invalid {} credentials in context {}: {}",
+                    kind, name, reason
+                )
+                .into(),
+            )
+        };
+        let kind = self.auth.kind();
+        match &self.auth {
+            Auth::Basic { username, .. } if username.is_empty() => {
+                return Err(invalid(kind, "the username is empty".to_string()))
+            }
+            Auth::Bearer { token } if !is_well_formed_token(token) => {
+                return Err(invalid(kind, "the token is malformed".to_string()))
+            }
+            Auth::ApiKeyQuery { key } if key.is_empty() => {
+                return Err(invalid(kind, "the key is empty".to_string()))
+            }
+            _ => {}
+        }
+
+        let (issuer_url, client_id, access_token, refresh_token) = match &self.auth {
+            Auth::Oidc {
+                issuer_url,
+                client_id,
+                access_token,
+                refresh_token,
+                expires_at,
+            } if *expires_at <= Utc::now() + Duration::seconds(OIDC_EXPIRY_MARGIN_SECS) => (
+                issuer_url.clone(),
+                client_id.clone(),
+                access_token.clone(),
+                refresh_token.clone(),
+            ),
+            _ => return Ok(self),
+        };
+        let refresh_token = match refresh_token {
+            Some(token) => {
+                keychain::resolve(token).map_err(|err| invalid(kind, err.to_string()))?
+            }
+            None => {
+                tracing::warn!(
+                    "The OIDC token of context {} has expired and cannot be refreshed, run `apicurio-sync context login` again",
+                    name
+                );
+                return Ok(self);
+            }
+        };
+        tracing::debug!("Refreshing the expired OIDC token of context {}", name);
+        let tokens = refresh_tokens(&issuer_url, &client_id, &refresh_token)
+            .await
+            .map_err(|err| {
+                invalid(
+                    kind,
+                    format!(
+                        "the token has expired and could not be refreshed ({}), run `apicurio-sync context login` again",
+                        err
+                    ),
+                )
+            })?;
+        self.auth = Auth::Oidc {
+            issuer_url,
+            client_id,
+            access_token: tokens.access_token,
+            refresh_token: tokens.refresh_token,
+            expires_at: tokens.expires_at,
+        };
+
+        let mut saved = self.clone();
+        if keychain::is_reference(&access_token) {
+            saved.store_secrets();
+        }
+        match saved.write_auth(ctx_path).await {
+            Ok(true) => {}
+            Ok(false) => tracing::debug!(
+                "Not saving the refreshed OIDC token: {} has no context {}",
+                ctx_path.display(),
+                name
+            ),
+            Err(err) => tracing::warn!(
+                "Failed to save the refreshed OIDC token to {}: {}",
+                ctx_path.display(),
+                err
+            ),
+        }
+        Ok(self)
+    }
+
+    /// Replaces the context file with an empty one, e.g. to recover from a corrupted file.
+    pub async fn reset_file(path: &Path) -> Result<(), Error> {
+        Self::write_empty_file(path, true).await
+    }
+
     async fn read_file(path: &Path) -> Result<ContextFile, Error> {
         let file = File::open(path).await?;
-        serde_json::from_reader(file.into_std().await).map_err(Into::into)
+        Self::parse_file(path, file).await
+    }
+
+    async fn parse_file(path: &Path, file: File) -> Result<ContextFile, Error> {
+        serde_json::from_reader(file.into_std().await).map_err(|err| {
+            Error::setup(format!(
+                "Failed to parse context file {}: {}",
+                path.display(),
+                err
+            ))
+        })
     }
 
     async fn write_file(content: &ContextFile, path: &Path, replace: bool) -> Result<(), Error> {
@@ -135,6 +413,7 @@ impl Context {
         let file = OpenOptions::new()
             .write(true)
             .truncate(replace)
+            .create(replace)
             .create_new(!replace)
             .open(path)
             .await?;
@@ -145,17 +424,26 @@ impl Context {
 #[derive(Debug, Default, Deserialize, Serialize)]
 struct ContextFile {
     current_context: Option<String>,
-    contexts: HashMap<String, RegistryContext>,
+    /// Registry used, without credentials, when there is no current context.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_registry_url: Option<Url>,
+    /// Kept in the order contexts were added, so that listing them is predictable.
+    contexts: IndexMap<String, RegistryContext>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 struct RegistryContext {
     url: Url,
     #[serde(default)]
     auth: Auth,
+    /// Command run on every invocation to get the credentials, instead of storing them in `auth`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    auth_helper: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    extra_headers: HashMap<String, String>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Auth {
     Oidc {
@@ -169,14 +457,293 @@ pub enum Auth {
         username: String,
         password: Option<String>,
     },
+    /// Only obtained at runtime from an auth helper.
+    Bearer { token: String },
+    /// An API key sent as a query parameter rather than a header.
+    ApiKeyQuery { key: String },
     #[serde(other)]
+    #[default]
     None,
 }
 
-impl Default for Auth {
-    fn default() -> Self {
-        Self::None
+/// Describes the credentials without revealing any secret.
+impl Display for Auth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Auth::Oidc {
+                issuer_url,
+                expires_at,
+                ..
+            } => write!(
+                f,
+                "oidc (issuer: {}, expires: {})",
+                issuer_url,
+                expires_at.to_rfc3339()
+            ),
+            Auth::Basic { username, .. } => write!(f, "basic (username: {})", username),
+            Auth::Bearer { .. } => write!(f, "bearer"),
+            Auth::ApiKeyQuery { .. } => write!(f, "api key (query parameter)"),
+            Auth::None => write!(f, "none"),
+        }
+    }
+}
+
+impl Auth {
+    /// Short name of the authentication method, without any detail.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Auth::Oidc { .. } => "oidc",
+            Auth::Basic { .. } => "basic",
+            Auth::Bearer { .. } => "bearer",
+            Auth::ApiKeyQuery { .. } => "apikey",
+            Auth::None => "none",
+        }
+    }
+
+    fn store_secrets(self, context_name: &str) -> Self {
+        let store = |field: &str, value: String| match keychain::store(context_name, field, &value)
+        {
+            Ok(reference) => reference,
+            Err(err) => {
+                tracing::warn!(
+                    "Keychain unavailable, storing {} in plaintext: {}",
+                    field,
+                    err
+                );
+                value
+            }
+        };
+        match self {
+            Auth::Oidc {
+                issuer_url,
+                client_id,
+                access_token,
+                refresh_token,
+                expires_at,
+            } => Auth::Oidc {
+                issuer_url,
+                client_id,
+                access_token: store("access_token", access_token),
+                refresh_token: refresh_token.map(|token| store("refresh_token", token)),
+                expires_at,
+            },
+            Auth::Basic { username, password } => Auth::Basic {
+                username,
+                password: password.map(|password| store("password", password)),
+            },
+            Auth::Bearer { token } => Auth::Bearer {
+                token: store("token", token),
+            },
+            Auth::ApiKeyQuery { key } => Auth::ApiKeyQuery {
+                key: store("api_key", key),
+            },
+            Auth::None => Auth::None,
+        }
+    }
+
+    fn resolve_secrets(self) -> Result<Self, Error> {
+        Ok(match self {
+            Auth::Oidc {
+                issuer_url,
+                client_id,
+                access_token,
+                refresh_token,
+                expires_at,
+            } => Auth::Oidc {
+                issuer_url,
+                client_id,
+                access_token: keychain::resolve(access_token)?,
+                refresh_token: refresh_token.map(keychain::resolve).transpose()?,
+                expires_at,
+            },
+            Auth::Basic { username, password } => Auth::Basic {
+                username,
+                password: password.map(keychain::resolve).transpose()?,
+            },
+            Auth::Bearer { token } => Auth::Bearer {
+                token: keychain::resolve(token)?,
+            },
+            Auth::ApiKeyQuery { key } => Auth::ApiKeyQuery {
+                key: keychain::resolve(key)?,
+            },
+            Auth::None => Auth::None,
+        })
     }
 }
 
 mod auth {}
+
+/// Tokens are opaque, so only catch values that cannot possibly be valid, like the output of
+/// a broken auth helper. Tokens shaped like a JWT must have its three parts.
+fn is_well_formed_token(token: &str) -> bool {
+    !token.is_empty()
+        && !token.chars().any(|c| c.is_whitespace() || c.is_control())
+        && (!token.starts_with("eyJ") || token.split('.').count() == 3)
+}
+
+/// Secrets kept in the system keychain are referenced from the context file
+/// as `keychain:apicurio-sync/<context>/<field>`.
+mod keychain {
+    use crate::error::Error;
+
+    const PREFIX: &str = "keychain:";
+    #[cfg(feature = "keychain")]
+    const SERVICE: &str = "apicurio-sync";
+
+    #[cfg(feature = "keychain")]
+    pub fn store(context_name: &str, field: &str, value: &str) -> Result<String, Error> {
+        let username = format!("{}/{}", context_name, field);
+        keyring::Entry::new(SERVICE, &username)
+            .set_password(value)
+            .map_err(|err| Error::Auth(err.into()))?;
+        Ok(format!("{}{}/{}", PREFIX, SERVICE, username))
+    }
+
+    #[cfg(not(feature = "keychain"))]
+    pub fn store(_context_name: &str, _field: &str, _value: &str) -> Result<String, Error> {
+        Err(Error::setup(
+            "apicurio-sync was built without the keychain feature",
+        ))
+    }
+
+    pub fn is_reference(value: &str) -> bool {
+        value.starts_with(PREFIX)
+    }
+
+    /// Returns `value` unchanged unless it is a keychain reference.
+    pub fn resolve(value: String) -> Result<String, Error> {
+        match value.strip_prefix(PREFIX) {
+            Some(reference) => lookup(reference),
+            None => Ok(value),
+        }
+    }
+
+    #[cfg(feature = "keychain")]
+    fn lookup(reference: &str) -> Result<String, Error> {
+        let username = reference
+            .strip_prefix(SERVICE)
+            .and_then(|rest| rest.strip_prefix('/'))
+            .ok_or_else(|| Error::setup(format!("Invalid keychain reference {}", reference)))?;
+        keyring::Entry::new(SERVICE, username)
+            .get_password()
+            .map_err(|err| {
+                Error::Auth(format!("Failed to read {} from keychain: {}", reference, err).into())
+            })
+    }
+
+    #[cfg(not(feature = "keychain"))]
+    fn lookup(reference: &str) -> Result<String, Error> {
+        Err(Error::setup(format!(
+            "Context references keychain secret {}, but apicurio-sync was built without the keychain feature",
+            reference
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    #[tokio::test]
+    async fn write_auth_only_updates_the_auth_of_an_existing_entry() {
+        let path = temp_dir("write-auth").join("context.json");
+        Context::write_empty_file(&path, false).await.unwrap();
+        let mut saved = Context::new("dev".to_string(), "http://global".parse().unwrap());
+        saved.auth_helper = Some("helper".to_string());
+        saved.write(&path, true).await.unwrap();
+
+        let refreshed = Context::new_with_auth(
+            "dev".to_string(),
+            "http://from-env".parse().unwrap(),
+            Auth::Bearer {
+                token: "refreshed".to_string(),
+            },
+        );
+        assert!(refreshed.write_auth(&path).await.unwrap());
+        let ctx = Context::from_file(&path, None).await.unwrap().unwrap();
+        assert_eq!(ctx.registry_url.as_str(), "http://global/");
+        assert_eq!(ctx.auth_helper.as_deref(), Some("helper"));
+        assert!(matches!(ctx.auth, Auth::Bearer { token } if token == "refreshed"));
+
+        let unknown = Context::new("prod".to_string(), "http://from-env".parse().unwrap());
+        assert!(!unknown.write_auth(&path).await.unwrap());
+        assert!(Context::from_file(&path, Some("prod".to_string()))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn oidc_auth_display_hides_tokens() {
+        let auth = Auth::Oidc {
+            issuer_url: "https://issuer.example.com".to_string(),
+            client_id: "apicurio-sync".to_string(),
+            access_token: "access-secret".to_string(),
+            refresh_token: Some("refresh-secret".to_string()),
+            expires_at: "2021-06-01T00:00:00Z".parse().unwrap(),
+        };
+        let shown = auth.to_string();
+        assert_eq!(
+            shown,
+            "oidc (issuer: https://issuer.example.com, expires: 2021-06-01T00:00:00+00:00)"
+        );
+        assert!(!shown.contains("access-secret"));
+        assert!(!shown.contains("refresh-secret"));
+    }
+
+    #[test]
+    fn basic_auth_display_hides_password() {
+        let auth = Auth::Basic {
+            username: "bob".to_string(),
+            password: Some("hunter2".to_string()),
+        };
+        let shown = auth.to_string();
+        assert_eq!(shown, "basic (username: bob)");
+        assert!(!shown.contains("hunter2"));
+    }
+
+    #[test]
+    fn bearer_auth_display_hides_token() {
+        let auth = Auth::Bearer {
+            token: "bearer-secret".to_string(),
+        };
+        let shown = auth.to_string();
+        assert_eq!(shown, "bearer");
+        assert!(!shown.contains("bearer-secret"));
+    }
+
+    #[test]
+    fn api_key_auth_display_hides_key() {
+        let auth = Auth::ApiKeyQuery {
+            key: "key-secret".to_string(),
+        };
+        let shown = auth.to_string();
+        assert_eq!(shown, "api key (query parameter)");
+        assert!(!shown.contains("key-secret"));
+    }
+
+    #[test]
+    fn no_auth_display() {
+        assert_eq!(Auth::None.to_string(), "none");
+    }
+
+    #[tokio::test]
+    async fn contexts_are_listed_in_the_order_they_were_added() {
+        let path = temp_dir("list-contexts-order").join("context.json");
+        Context::write_empty_file(&path, false).await.unwrap();
+        for name in ["zeta", "alpha"] {
+            Context::new(name.to_string(), "http://registry".parse().unwrap())
+                .write(&path, false)
+                .await
+                .unwrap();
+        }
+
+        let (contexts, _) = Context::list_contexts(&path).await.unwrap();
+        let names: Vec<&str> = contexts
+            .iter()
+            .map(|ctx| ctx.context_name.as_str())
+            .collect();
+        assert_eq!(names, ["zeta", "alpha"]);
+    }
+}
@@ -1,36 +1,83 @@
 use std::fmt::{self, Display, Formatter};
+use std::process::{ExitCode, Termination};
 
 #[derive(Debug)]
 pub enum Error {
-    Http(reqwest::Error),
+    Http {
+        err: reqwest::Error,
+        body: Option<String>,
+    },
     Io(std::io::Error),
-    Parse(Box<dyn std::error::Error>),
+    Parse(Box<dyn std::error::Error + Send + Sync>),
     Setup(String),
-    Auth(Box<dyn std::error::Error>),
+    Auth(Box<dyn std::error::Error + Send + Sync>),
+    Timeout(String),
+    NotFound {
+        url: String,
+    },
+    Unauthorized {
+        url: String,
+    },
+    /// Several operations that ran concurrently failed.
+    Multiple(Vec<Error>),
 }
 
 impl Error {
     pub fn setup(msg: impl ToString) -> Self {
         Self::Setup(msg.to_string())
     }
+
+    /// The process exit code to use when the CLI fails with this error.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Timeout(_) => 8,
+            Error::Multiple(errors) => errors.iter().map(Error::exit_code).max().unwrap_or(1),
+            _ => 1,
+        }
+    }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            Error::Http(err) => err.fmt(f),
+            Error::Http {
+                err,
+                body: Some(body),
+            } => write!(f, "{}: {}", err, body),
+            Error::Http { err, body: None } => err.fmt(f),
             Error::Io(err) => err.fmt(f),
             Error::Setup(msg) => msg.fmt(f),
             Error::Parse(err) => err.fmt(f),
             Error::Auth(err) => err.fmt(f),
+            Error::Timeout(msg) => msg.fmt(f),
+            Error::NotFound { url } => write!(f, "not found: {}", url),
+            Error::Unauthorized { url } => write!(
+                f,
+                "not authorized to access {}, check the context credentials or log in again",
+                url
+            ),
+            Error::Multiple(errors) if errors.len() == 1 => errors[0].fmt(f),
+            Error::Multiple(errors) => {
+                write!(f, "{} operations failed:", errors.len())?;
+                errors.iter().try_for_each(|err| write!(f, "\n  {}", err))
+            }
         }
     }
 }
 
+/// Prints the error to stderr and exits with its exit code, as the `Termination` impl of
+/// `Result` would print its `Debug` representation and always exit with 1.
+impl Termination for Error {
+    fn report(self) -> ExitCode {
+        eprintln!("Error: {}", self);
+        ExitCode::from(self.exit_code())
+    }
+}
+
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
-            Error::Http(err) => Some(err),
+            Error::Http { err, .. } => Some(err),
             Error::Io(err) => Some(err),
             Error::Parse(err) => Some(err.as_ref()),
             Error::Auth(err) => Some(err.as_ref()),
@@ -41,7 +88,7 @@ impl std::error::Error for Error {
 
 impl From<reqwest::Error> for Error {
     fn from(err: reqwest::Error) -> Self {
-        Self::Http(err)
+        Self::Http { err, body: None }
     }
 }
 
@@ -68,3 +115,22 @@ impl From<url::ParseError> for Error {
         Self::Parse(Box::new(err))
     }
 }
+
+impl<RE> From<openidconnect::DiscoveryError<RE>> for Error
+where
+    RE: std::error::Error + Send + Sync + 'static,
+{
+    fn from(err: openidconnect::DiscoveryError<RE>) -> Self {
+        Self::Auth(Box::new(err))
+    }
+}
+
+impl<RE, T> From<openidconnect::RequestTokenError<RE, T>> for Error
+where
+    RE: std::error::Error + Send + Sync + 'static,
+    T: openidconnect::ErrorResponse + Send + Sync + 'static,
+{
+    fn from(err: openidconnect::RequestTokenError<RE, T>) -> Self {
+        Self::Auth(Box::new(err))
+    }
+}
@@ -1,27 +1,46 @@
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
 use serde::{Deserialize, Serialize};
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 
+use crate::checksum::ChecksumAlgorithm;
 use crate::config::Config;
 use crate::context;
 use crate::error::Error;
-use crate::provider::Provider;
+use crate::provider::{ArtifactType, Provider};
+
+const LOCK_TIMEOUT_ENVAR: &str = "APICURIO_SYNC_LOCKFILE_TIMEOUT_SECS";
+const DEFAULT_LOCK_TIMEOUT_SECS: u64 = 30;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct LockFile {
+    /// Used for every content hash in the lockfile. Lockfiles written before it existed use SHA-256.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum_algorithm: Option<ChecksumAlgorithm>,
     pub pull: HashMap<PathBuf, PullArtifactRef>,
+    #[serde(default)]
+    pub push: HashMap<PathBuf, PushArtifactRecord>,
     #[serde(skip)]
     path: PathBuf,
+    /// Exclusive lock on the `.lock.lck` sidecar, held for as long as this value lives.
+    #[serde(skip)]
+    guard: Option<std::fs::File>,
 }
 
 impl LockFile {
     fn empty(path: PathBuf) -> Self {
         Self {
+            checksum_algorithm: Some(ChecksumAlgorithm::default()),
             pull: HashMap::new(),
+            push: HashMap::new(),
             path,
+            guard: None,
         }
     }
 
@@ -30,36 +49,62 @@ impl LockFile {
         provider: &impl Provider,
         auth: &context::Auth,
     ) -> Result<Self, Error> {
+        let mut lock_file = Self::load_for_config(config).await?;
+        lock_file
+            .generate(config, provider, false, false, auth)
+            .await?;
+        Ok(lock_file)
+    }
+
+    /// Loads the lockfile as it is on disk, without reconciling it with the registry.
+    pub async fn load_for_config(config: &Config) -> Result<Self, Error> {
         let path = &config.path;
         let path = path
             .with_file_name(path.file_name().unwrap())
             .with_extension("lock");
+        let guard = lock(&path).await?;
         let lock_file = match File::open(&path).await {
             Ok(file) => Some(file),
             Err(err) => match err.kind() {
-                std::io::ErrorKind::NotFound => None,
-                _ => return Err(err.into()),
+                ErrorKind::NotFound => None,
+                _ => {
+                    return Err(Error::setup(format!(
+                        "Failed to open lockfile {}: {}",
+                        path.display(),
+                        err
+                    )))
+                }
             },
         };
-        let mut lock_file = if let Some(lock_file) = lock_file {
+        if let Some(lock_file) = lock_file {
             let mut lock_file: LockFile = serde_json::from_reader(lock_file.into_std().await)
-                .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+                .map_err(|err| {
+                    Error::setup(format!(
+                        "Failed to parse lockfile {}: {}",
+                        path.display(),
+                        err
+                    ))
+                })?;
             lock_file.path = path;
-            lock_file
+            lock_file.guard = Some(guard);
+            Ok(lock_file)
         } else {
-            Self::empty(path)
-        };
-        lock_file.generate(config, provider, false, auth).await?;
-        Ok(lock_file)
+            let mut lock_file = Self::empty(path);
+            lock_file.guard = Some(guard);
+            Ok(lock_file)
+        }
     }
 
-    pub async fn update(
+    /// Refreshes every pull artifact from the registry, returning the version change of each one.
+    pub async fn update_with_changes(
         &mut self,
         config: &Config,
         provider: &impl Provider,
+        warn_on_outdated_pins: bool,
         auth: &context::Auth,
-    ) -> Result<(), Error> {
-        self.generate(config, provider, true, auth).await
+    ) -> Result<Vec<LockfileChange>, Error> {
+        self.generate(config, provider, true, warn_on_outdated_pins, auth)
+            .await
     }
 
     async fn generate(
@@ -67,21 +112,30 @@ impl LockFile {
         config: &Config,
         provider: &impl Provider,
         update: bool,
+        warn_on_outdated_pins: bool,
         auth: &context::Auth,
-    ) -> Result<(), Error> {
-        let pull = &config.pull;
-        if pull.is_empty() {
-            self.pull = HashMap::new();
-        }
-
-        let mut pull_inserted = HashSet::new();
-        for artifact in pull {
-            pull_inserted.insert(artifact.path.clone());
+    ) -> Result<Vec<LockfileChange>, Error> {
+        let mut changes = Vec::new();
+        for artifact in &config.pull {
             if !update && self.pull.contains_key(&artifact.path) {
                 continue;
             }
 
-            let locked = if let Some(version) = &artifact.version {
+            let version = match (&artifact.version, &artifact.version_expression) {
+                (Some(version), _) => Some(version.clone()),
+                (None, Some(expression)) => Some(
+                    provider
+                        .resolve_version_expression(
+                            &artifact.group,
+                            &artifact.artifact,
+                            expression,
+                            auth,
+                        )
+                        .await?,
+                ),
+                (None, None) => None,
+            };
+            let mut locked = if let Some(version) = &version {
                 let metadata = provider
                     .fetch_artifact_version_metadata(
                         &artifact.group,
@@ -90,10 +144,28 @@ impl LockFile {
                         auth,
                     )
                     .await?;
+                if warn_on_outdated_pins && artifact.version.is_some() {
+                    let latest = provider
+                        .fetch_artifact_metadata(&artifact.group, &artifact.artifact, auth)
+                        .await?;
+                    if latest.version != metadata.version {
+                        tracing::warn!(
+                            "artifact {}/{} is pinned to {} but latest is {}",
+                            artifact.group,
+                            artifact.artifact,
+                            metadata.version,
+                            latest.version
+                        );
+                    }
+                }
                 PullArtifactRef {
                     group: metadata.group_id,
                     artifact: metadata.id,
                     version: metadata.version,
+                    artifact_type: Some(metadata.artifact_type),
+                    // Versions are immutable, so they are last modified when created
+                    modified_on: Some(metadata.created_on),
+                    content_hash: None,
                 }
             } else {
                 let metadata = provider
@@ -103,25 +175,168 @@ impl LockFile {
                     group: metadata.group_id,
                     artifact: metadata.id,
                     version: metadata.version,
+                    artifact_type: Some(metadata.artifact_type),
+                    modified_on: Some(metadata.modified_on),
+                    content_hash: None,
                 }
             };
+            let previous = self.pull.get(&artifact.path);
+            let old_version = previous.map(|locked| locked.version.clone());
+            // The content of a version never changes, so its hash stays valid
+            locked.content_hash = previous
+                .filter(|previous| {
+                    previous.group == locked.group
+                        && previous.artifact == locked.artifact
+                        && previous.version == locked.version
+                })
+                .and_then(|previous| previous.content_hash.clone());
+            changes.push(LockfileChange {
+                path: artifact.path.clone(),
+                changed: old_version.as_ref() != Some(&locked.version),
+                old_version,
+                new_version: locked.version.clone(),
+            });
             self.pull.insert(artifact.path.clone(), locked);
         }
 
-        let keys: HashSet<PathBuf> = self.pull.keys().cloned().collect();
-        for key in keys.difference(&pull_inserted) {
-            self.pull.remove(key);
+        self.prune(config);
+        self.save().await?;
+        Ok(changes)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn checksum_algorithm(&self) -> ChecksumAlgorithm {
+        self.checksum_algorithm.unwrap_or_default()
+    }
+
+    /// Removes the entries of artifacts that are no longer in the config, returning their paths.
+    pub fn prune(&mut self, config: &Config) -> Vec<PathBuf> {
+        let configured: HashSet<&PathBuf> = config.pull.iter().map(|a| &a.path).collect();
+        let mut pruned: Vec<PathBuf> = self
+            .pull
+            .keys()
+            .filter(|path| !configured.contains(path))
+            .cloned()
+            .collect();
+        pruned.sort();
+        for path in &pruned {
+            self.pull.remove(path);
         }
+        pruned
+    }
+
+    /// Removes every entry and saves the lockfile, keeping its checksum algorithm,
+    /// so that the next `update` locks every artifact again from scratch.
+    pub async fn clean(&mut self) -> Result<(), Error> {
+        self.pull = HashMap::new();
+        self.push = HashMap::new();
+        self.save().await
+    }
 
+    pub async fn save(&self) -> Result<(), Error> {
         let mut file = File::create(&self.path).await?;
         let content = serde_json::to_vec_pretty(&self).expect("LockFile JSON render");
         file.write_all(&content).await.map_err(Error::from)
     }
 }
 
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if let Some(guard) = &self.guard {
+            let _ = guard.unlock();
+        }
+    }
+}
+
+/// Takes an exclusive advisory lock on the sidecar of the lockfile at `path`, so that concurrent
+/// runs in the same workspace don't overwrite each other's changes.
+async fn lock(path: &Path) -> Result<std::fs::File, Error> {
+    let timeout = std::env::var(LOCK_TIMEOUT_ENVAR)
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(DEFAULT_LOCK_TIMEOUT_SECS);
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path.with_extension("lock.lck"))?;
+    loop {
+        match file.try_lock_exclusive() {
+            Ok(()) => return Ok(file),
+            Err(err) if err.kind() == fs2::lock_contended_error().kind() => {
+                if Instant::now() >= deadline {
+                    return Err(std::io::Error::new(
+                        ErrorKind::WouldBlock,
+                        "lockfile is locked by another process",
+                    )
+                    .into());
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LockfileChange {
+    pub path: PathBuf,
+    pub old_version: Option<String>,
+    pub new_version: String,
+    pub changed: bool,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct PullArtifactRef {
     pub group: String,
     pub artifact: String,
     pub version: String,
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub artifact_type: Option<ArtifactType>,
+    /// When the locked version was last modified in the registry, as reported by it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified_on: Option<String>,
+    /// Hex encoded SHA-256 of the content, recorded after the first pull of the locked version.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+}
+
+/// What the registry assigned to a local file the last time it was pushed.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PushArtifactRecord {
+    pub group: String,
+    pub artifact: String,
+    pub version: String,
+    pub global_id: u64,
+    pub content_id: u64,
+    /// Hex encoded SHA-256 of the pushed content.
+    pub content_hash: String,
+    pub pushed_at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    #[tokio::test]
+    async fn corrupt_lockfile_error_names_the_lockfile() {
+        let workdir = temp_dir("corrupt-lockfile");
+        let path = workdir.join("apicurio-sync.lock");
+        std::fs::write(&path, "{ not json").unwrap();
+        let mut config = Config::parse("{}").unwrap();
+        config.path = workdir.join("apicurio-sync.yaml");
+
+        match LockFile::load_for_config(&config).await {
+            Err(Error::Setup(msg)) => {
+                assert!(msg.contains(&path.display().to_string()), "{}", msg)
+            }
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("corrupt lockfile was loaded"),
+        }
+    }
 }
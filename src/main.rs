@@ -1,35 +1,51 @@
 #[macro_use]
 extern crate lazy_static;
 
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
 use std::future::Future;
-use std::io::BufRead;
+use std::io::{BufRead, ErrorKind, Read, Write};
 use std::path::{Path, PathBuf};
+use std::process::{ExitCode, Termination};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use chrono::{DateTime, Utc};
 use structopt::StructOpt;
-use tokio::fs::File;
-use tokio::io::AsyncReadExt;
 use url::Url;
 
 use crate::auth::AuthProvider;
+use crate::auth::apikey::ApiKeyQueryProvider;
 use crate::auth::basic::BasicAuthProvider;
+use crate::auth::helper::HelperProvider;
 use crate::auth::oidc::OidcProvider;
+use crate::checksum::{file_hash, ChecksumAlgorithm};
 use crate::client::Client;
-use crate::config::Config;
+use crate::config::document::Document;
+use crate::config::{Config, Template};
 use crate::context::Context;
 use crate::error::Error;
 use crate::lockfile::LockFile;
 use crate::plan::Plan;
-use crate::provider::{NoopProvider, Provider};
+use crate::provider::{
+    ArtifactState, ArtifactType, NoopProvider, Provider, PushArtifactMetadata, PushContent,
+};
+use crate::sync::{SyncContext, SyncReport};
 
 mod auth;
+mod checksum;
 mod client;
 mod config;
 mod context;
 mod error;
 mod lockfile;
+mod notification;
 mod plan;
 mod provider;
 mod sync;
+#[cfg(test)]
+mod test_util;
 
 lazy_static! {
     static ref CONFIG_DIR: String = {
@@ -39,47 +55,374 @@ lazy_static! {
     static ref CONTEXT_FILE: String = format!("{}/context.json", CONFIG_DIR.as_str());
 }
 
+const LOCAL_CONTEXT_FILE: &str = ".apicurio-context.json";
+
 #[derive(Debug, StructOpt)]
 enum Command {
     #[structopt(
         about = "Updates the project lockfile with the registry without updating the artifacts themselves",
         long_about = "Updates the project lockfile with the registry, by fetching the required version (if specified) or the latest version from the API. This operation does not update the artifacts themselves. Rerun `sync` to do so."
     )]
-    Update,
+    Update {
+        #[structopt(short, long, help = "Also list artifacts whose version did not change")]
+        verbose: bool,
+    },
     #[structopt(long_about = "Initializes an empty config file")]
-    Init,
+    Init {
+        #[structopt(long, help = "Overwrite the config file if it already exists")]
+        force: bool,
+        #[structopt(
+            long,
+            help = "The config template to write, either `minimal` or `full` (with commented examples)",
+            default_value = "full"
+        )]
+        template: Template,
+    },
     #[structopt(
         about = "Synchronizes artifacts with the registry",
         long_about = "Synchronizes artifacts with the registry. Push operations upload artifacts to the registry, while pull operations downloads them into the specified local folder"
     )]
-    Sync,
+    Sync {
+        #[structopt(
+            long,
+            help = "Only pull artifacts modified in the registry after this RFC 3339 timestamp, e.g. 2021-10-01T12:00:00Z. Artifacts locked before this was recorded are always pulled"
+        )]
+        since: Option<DateTime<Utc>>,
+        #[structopt(
+            long,
+            help = "The format of the summary printed once done, either `table` or `json`",
+            default_value = "table"
+        )]
+        format: Format,
+    },
     #[structopt(
         about = "Work with context",
         long_about = "Manipulate the local CLI context. The context is used to configure registries and their authentication credentials"
     )]
     Context(ContextCommand),
-    #[structopt(about = "Print registry information for debugging purposes")]
-    Info,
+    #[structopt(
+        about = "Edit the config file",
+        long_about = "Edit the config file in place, preserving its comments and formatting"
+    )]
+    Config(ConfigCommand),
+    #[structopt(
+        about = "Print the resolved sync plan",
+        long_about = "Print what `sync` would do: every artifact with its local path, direction, coordinates and resolved version. No artifact content is fetched"
+    )]
+    Plan {
+        #[structopt(
+            long,
+            help = "The output format, either `table` or `json`",
+            default_value = "table"
+        )]
+        format: Format,
+    },
+    #[structopt(
+        about = "Work with the lockfile",
+        long_about = "Manipulate the project lockfile directly, without contacting the registry"
+    )]
+    Lockfile(LockfileCommand),
+    #[structopt(about = "Compute content hashes like the ones recorded in the lockfile")]
+    Checksum(ChecksumCommand),
+    #[structopt(
+        about = "Print connection details and registry information for debugging purposes"
+    )]
+    Info {
+        #[structopt(
+            long,
+            help = "The output format, either `table` or `json`",
+            default_value = "table"
+        )]
+        format: Format,
+    },
+    #[structopt(
+        name = "pull-artifact",
+        alias = "fetch-artifact",
+        about = "Download a single artifact, without a config file",
+        long_about = "Download a single artifact version from the registry in the current context, bypassing the config file and the lockfile entirely"
+    )]
+    FetchArtifact {
+        #[structopt(long, help = "The artifact group")]
+        group: String,
+        #[structopt(long, help = "The artifact ID")]
+        artifact: String,
+        #[structopt(
+            long,
+            help = "The version to download, a SemVer range like `^1.2`, or `latest`. Defaults to the latest version"
+        )]
+        version: Option<String>,
+        #[structopt(
+            short,
+            long,
+            help = "The file to write the artifact to. Defaults to stdout",
+            parse(from_os_str)
+        )]
+        output: Option<PathBuf>,
+    },
+    #[structopt(
+        about = "Upload a single artifact, without a config file",
+        long_about = "Upload a single artifact to the registry in the current context, bypassing the config file and the lockfile entirely"
+    )]
+    PushArtifact {
+        #[structopt(long, help = "The artifact group")]
+        group: String,
+        #[structopt(long, help = "The artifact ID")]
+        artifact: String,
+        #[structopt(
+            long,
+            help = "The file to upload. Defaults to stdin",
+            parse(from_os_str)
+        )]
+        file: Option<PathBuf>,
+        #[structopt(long = "type", help = "The artifact type, e.g. `AVRO` or `OPENAPI`")]
+        artifact_type: Option<ArtifactType>,
+        #[structopt(long, help = "The artifact name")]
+        name: Option<String>,
+        #[structopt(long, help = "The artifact description")]
+        description: Option<String>,
+        #[structopt(
+            long,
+            help = "The version to create. Assigned by the registry if absent"
+        )]
+        version: Option<String>,
+    },
+    #[structopt(
+        about = "Find push artifacts that are missing from the registry or from the config",
+        long_about = "Compares the push artifacts in the config with the artifacts in the registry, for each group mentioned in the config. Reports artifacts that will be created on the next sync and artifacts in the registry that are not present in the config (potential orphans)"
+    )]
+    CheckUnused {
+        #[structopt(
+            long,
+            help = "Delete orphaned artifacts from the registry, after asking for confirmation"
+        )]
+        delete_orphans: bool,
+    },
+    #[structopt(about = "Work with artifact groups in the registry")]
+    Group(GroupCommand),
+    #[structopt(
+        about = "Mark an artifact version as deprecated",
+        long_about = "Mark an artifact version as deprecated. Deprecated versions can still be pulled, with a warning"
+    )]
+    DeprecateVersion(VersionArgs),
+    #[structopt(about = "Enable a disabled or deprecated artifact version")]
+    EnableVersion(VersionArgs),
+    #[structopt(
+        about = "Disable an artifact version",
+        long_about = "Disable an artifact version. Disabled versions are skipped when pulling"
+    )]
+    DisableVersion(VersionArgs),
+}
+
+#[derive(Debug, StructOpt)]
+struct VersionArgs {
+    #[structopt(long, help = "The artifact group")]
+    group: String,
+    #[structopt(long, help = "The artifact ID")]
+    artifact: String,
+    #[structopt(long, help = "The artifact version")]
+    version: String,
+}
+
+#[derive(Debug, StructOpt)]
+enum GroupCommand {
+    #[structopt(long_about = "Print the metadata of a group")]
+    Info {
+        #[structopt(help = "The group ID")]
+        group: String,
+        #[structopt(
+            long,
+            help = "The output format, either `table` or `json`",
+            default_value = "table"
+        )]
+        format: Format,
+    },
+    #[structopt(long_about = "Create an empty group")]
+    Create {
+        #[structopt(help = "The group ID")]
+        group: String,
+        #[structopt(long, help = "What the group is about")]
+        description: Option<String>,
+    },
+    #[structopt(
+        long_about = "Delete a group and every artifact in it, after asking for confirmation"
+    )]
+    Delete {
+        #[structopt(help = "The group ID")]
+        group: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Table,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            _ => Err(format!(
+                "unknown format `{}`, expected `table` or `json`",
+                s
+            )),
+        }
+    }
 }
 
 #[derive(Debug, StructOpt)]
 enum ContextCommand {
     #[structopt(long_about = "Print current context")]
-    Current,
+    Current {
+        #[structopt(
+            long,
+            help = "The output format, either `table` or `json`",
+            default_value = "table"
+        )]
+        format: Format,
+    },
     #[structopt(long_about = "Init context file")]
-    Init,
+    Init {
+        #[structopt(long, help = "Overwrite the context file if it already exists")]
+        force: bool,
+    },
     #[structopt(long_about = "Set context properties")]
     Set {
         #[structopt(short, long, help = "The registry URL to set")]
         url: Option<Url>,
         #[structopt(short, long, help = "Set this context as current")]
         current: bool,
-        context_name: String,
+        #[structopt(
+            long,
+            help = "Clear the authentication credentials stored for this context"
+        )]
+        remove_auth: bool,
+        #[structopt(
+            long,
+            help = "A command that prints the credentials for the registry URL it gets as argument. Pass an empty value to remove it"
+        )]
+        auth_helper: Option<String>,
+        #[structopt(
+            long,
+            help = "Check that the registry is reachable with the new settings before saving them"
+        )]
+        validate: bool,
+        #[structopt(
+            long = "header",
+            help = "An extra HTTP header to send with every request, as KEY=VALUE. Can be repeated",
+            parse(try_from_str = parse_header),
+            number_of_values = 1
+        )]
+        headers: Vec<(String, String)>,
+        #[structopt(
+            long = "remove-header",
+            help = "Remove a previously set extra HTTP header. Can be repeated",
+            number_of_values = 1
+        )]
+        remove_headers: Vec<String>,
+        #[structopt(
+            long,
+            help = "Set the registry URL used, without credentials, when no context is current"
+        )]
+        set_default_url: Option<Url>,
+        #[structopt(required_unless = "set-default-url")]
+        context_name: Option<String>,
     },
     #[structopt(long_about = "Print all context configurations")]
     Show,
+    #[structopt(long_about = "List all contexts with their registry URL and authentication")]
+    List {
+        #[structopt(long, help = "Only show the current context")]
+        active_only: bool,
+    },
     #[structopt(long_about = "Authenticate with the current registry")]
-    Login(LoginCommand),
+    Login {
+        #[structopt(
+            long,
+            help = "Store tokens in the system keychain instead of the context file. Requires the keychain feature"
+        )]
+        use_keychain: bool,
+        #[structopt(subcommand)]
+        cmd: LoginCommand,
+    },
+    #[structopt(
+        long_about = "Clear the authentication credentials stored for a context. Same as `context set --remove-auth`"
+    )]
+    Logout {
+        #[structopt(help = "The context to log out from. Defaults to the current context")]
+        context_name: Option<String>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum ConfigCommand {
+    #[structopt(long_about = "Add a pull artifact to the config file")]
+    AddPull {
+        #[structopt(short, long, help = "The artifact group")]
+        group: String,
+        #[structopt(short, long, help = "The artifact ID")]
+        artifact: String,
+        #[structopt(
+            short,
+            long,
+            help = "The version to pin. If omitted, the latest version is used"
+        )]
+        version: Option<String>,
+        #[structopt(help = "The local path to pull the artifact into", parse(from_os_str))]
+        path: PathBuf,
+    },
+    #[structopt(long_about = "Check the config file for mistakes without contacting the registry")]
+    Validate {
+        #[structopt(
+            long,
+            help = "Also check the config file against its JSON Schema, rejecting unknown fields"
+        )]
+        strict: bool,
+    },
+}
+
+fn parse_header(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("invalid header `{}`, expected KEY=VALUE", s))?;
+    http::header::HeaderName::from_bytes(name.as_bytes())
+        .map_err(|err| format!("invalid header name `{}`: {}", name, err))?;
+    http::header::HeaderValue::from_str(value)
+        .map_err(|err| format!("invalid value for header `{}`: {}", name, err))?;
+    Ok((name.to_string(), value.to_string()))
+}
+
+#[derive(Debug, StructOpt)]
+enum LockfileCommand {
+    #[structopt(
+        long_about = "Remove lockfile entries for artifacts that are no longer in the config"
+    )]
+    Prune,
+    #[structopt(
+        long_about = "Remove every lockfile entry, so that the next `update` locks all artifacts again, e.g. after switching to another registry"
+    )]
+    Clean {
+        #[structopt(long, help = "Confirm that every lockfile entry should be removed")]
+        confirm: bool,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum ChecksumCommand {
+    #[structopt(long_about = "Print the content hash of each file, one per line")]
+    Compute {
+        #[structopt(
+            long,
+            help = "The hash function, one of `sha256`, `sha512` or `blake3`",
+            default_value = "sha256"
+        )]
+        algorithm: ChecksumAlgorithm,
+        #[structopt(parse(from_os_str), required = true)]
+        files: Vec<PathBuf>,
+    },
 }
 
 #[derive(Debug, StructOpt)]
@@ -103,6 +446,12 @@ enum LoginCommand {
             default_value = "9876"
         )]
         port: u16,
+        #[structopt(
+            long,
+            help = "How many seconds to wait for the login to complete in the browser",
+            default_value = "300"
+        )]
+        login_timeout_secs: u64,
         issuer_url: String,
     },
     #[structopt(long_about = "Authenticate using a simple username/password pair")]
@@ -115,9 +464,19 @@ enum LoginCommand {
         )]
         password_stdin: bool,
     },
+    #[structopt(
+        long_about = "Authenticate with an API key sent as the apicurio_registry_api_key query parameter"
+    )]
+    ApikeyQuery {
+        #[structopt(long, help = "The API key to use")]
+        key: String,
+    },
 }
 
 #[derive(Debug, StructOpt)]
+#[structopt(
+    after_help = "Run `apicurio-sync sync` to synchronize the artifacts in the config file"
+)]
 struct Opts {
     #[structopt(
         short = "f",
@@ -137,68 +496,510 @@ struct Opts {
     parse(from_os_str),
     global = true)]
     context: PathBuf,
+    #[structopt(
+        long = "local-context",
+        help = "Use the project-local context file (.apicurio-context.json in the working directory), even if it does not exist yet",
+        global = true
+    )]
+    local_context: bool,
+    #[structopt(
+        long = "reset-context",
+        help = "Replace the context file with an empty one if it cannot be parsed",
+        global = true
+    )]
+    reset_context: bool,
     #[structopt(
         long = "cwd",
-        help = "The working directory to use. Every operation will happen inside this directory. Defaults to the current directory.",
+        help = "The working directory to use. Every operation will happen inside this directory, and relative paths (including --context-file) are resolved against it. Defaults to the current directory.",
         env = "APICURIO_SYNC_WORKDIR",
         parse(from_os_str),
         global = true
     )]
     cwd: Option<PathBuf>,
+    #[structopt(
+        long = "extra-config",
+        help = "Additional configuration files to merge into the main one. Can be repeated",
+        parse(from_os_str),
+        number_of_values = 1,
+        global = true
+    )]
+    extra_configs: Vec<PathBuf>,
+    #[structopt(
+        long,
+        help = "The config profile whose artifacts are merged into the base ones",
+        env = "APICURIO_SYNC_PROFILE",
+        global = true
+    )]
+    profile: Option<String>,
     #[structopt(
     short,
     long,
     help = "Whether to print debug logs or not",
     global = true)]
     debug: bool,
+    #[structopt(
+        long = "warn-on-outdated-pins",
+        help = "Warn when a pinned artifact version is not the latest one (the default)",
+        conflicts_with = "no-warn-on-outdated-pins",
+        global = true
+    )]
+    #[allow(dead_code)]
+    warn_on_outdated_pins: bool,
+    #[structopt(
+        long = "no-warn-on-outdated-pins",
+        help = "Do not warn when a pinned artifact version is not the latest one",
+        global = true
+    )]
+    no_warn_on_outdated_pins: bool,
+    #[structopt(
+        long,
+        help = "Allow artifact paths that are absolute or outside the working directory",
+        global = true
+    )]
+    allow_absolute_paths: bool,
+    #[structopt(
+        long,
+        help = "Keep pushing the remaining artifacts when one of them fails, then report the failures",
+        global = true
+    )]
+    continue_on_error: bool,
+    #[structopt(
+        long,
+        help = "Push artifacts even if their content did not change since the last push",
+        global = true
+    )]
+    force_push: bool,
+    #[structopt(
+        long,
+        help = "How many artifacts to push at the same time",
+        default_value = "5",
+        global = true
+    )]
+    max_concurrent_pushes: usize,
+    #[structopt(
+        long,
+        help = "How many artifacts to pull at the same time",
+        default_value = "5",
+        global = true
+    )]
+    max_concurrent_pulls: usize,
+    #[structopt(
+        long = "no-validate-content",
+        help = "Write pulled artifacts without checking that their content matches their type",
+        parse(from_flag = std::ops::Not::not),
+        global = true
+    )]
+    validate_content: bool,
+    #[structopt(
+        long = "no-atomic-writes",
+        help = "Write pulled artifacts in place instead of through a temporary file, for filesystems that do not support renames",
+        parse(from_flag = std::ops::Not::not),
+        global = true
+    )]
+    atomic_writes: bool,
+    #[structopt(
+        long,
+        help = "Abort the whole operation if it takes longer than this many seconds. Disabled if 0 or absent",
+        env = "APICURIO_SYNC_TIMEOUT_SECS",
+        global = true
+    )]
+    timeout_secs: Option<u64>,
+    #[structopt(flatten)]
+    http: HttpOpts,
     #[structopt(subcommand)]
     cmd: Option<Command>,
 }
 
-async fn run() -> Result<(), Error> {
-    let opts: Opts = Opts::from_args();
+#[derive(Debug, StructOpt)]
+struct HttpOpts {
+    #[structopt(
+        long,
+        help = "Timeout of every single request to the registry, in seconds",
+        global = true
+    )]
+    http_timeout_secs: Option<u64>,
+    #[structopt(
+        long,
+        help = "How many times to retry requests that fail because of connection problems, gateway errors or rate limiting. Rate limited requests are retried once even with 0",
+        default_value = "0",
+        global = true
+    )]
+    retries: u32,
+    #[structopt(
+        long,
+        help = "PEM encoded CA certificate to trust when connecting to the registry",
+        parse(from_os_str),
+        global = true
+    )]
+    ca_cert: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "Proxy to use for every request to the registry",
+        global = true
+    )]
+    proxy: Option<Url>,
+    #[structopt(
+        long,
+        help = "Do not verify the registry TLS certificate. Only use this for local testing",
+        global = true
+    )]
+    insecure: bool,
+    #[structopt(
+        long,
+        help = "Stream artifacts larger than this many bytes to the registry instead of reading them in memory. Streamed pushes are not retried",
+        default_value = "1048576",
+        global = true
+    )]
+    large_artifact_threshold_bytes: usize,
+    #[structopt(
+        long = "pool-max-idle",
+        help = "How many idle connections to the registry to keep open for reuse. Higher values speed up large syncs",
+        default_value = "10",
+        global = true
+    )]
+    pool_max_idle: usize,
+    #[structopt(
+        long = "pool-idle-timeout",
+        help = "How many seconds an idle connection to the registry is kept open",
+        global = true
+    )]
+    pool_idle_timeout_secs: Option<u64>,
+}
+
+async fn run(opts: Opts) -> Result<(), Error> {
     let debug = opts.debug;
     if std::env::var_os("RUST_LOG").is_none() {
         std::env::set_var("RUST_LOG", format!("apicurio_sync={}", if debug { "debug" } else { "info" }));
     }
 
-    tracing_subscriber::fmt::init();
-    let workdir = opts
-        .cwd
-        .unwrap_or_else(|| std::env::current_dir().expect("current_dir"));
+    // Logs go to stderr, so that command output on stdout can be piped into other tools
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
+    let cmd = match &opts.cmd {
+        Some(cmd) => cmd,
+        None => {
+            Opts::clap()
+                .print_help()
+                .map_err(|err| Error::setup(err.to_string()))?;
+            println!();
+            return Ok(());
+        }
+    };
+    let workdir = enter_workdir(opts.cwd.as_deref())?;
+    startup_checks(&workdir);
     let cfg_file = workdir.join(opts.config);
-    if let Some(Command::Init) = opts.cmd {
-        return init(cfg_file, &NoopProvider, &context::Auth::None).await;
+    if let Some(Command::Init { force, template }) = opts.cmd {
+        init(
+            cfg_file,
+            force,
+            template,
+            &NoopProvider::strict(),
+            &context::Auth::None,
+        )
+        .await?;
+        if !opts.context.exists() && !workdir.join(LOCAL_CONTEXT_FILE).exists() {
+            tracing::info!(
+                "No context file found. Run `apicurio-sync context init` to create one, then `apicurio-sync context set` to point it to your registry"
+            );
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Config(cmd)) = opts.cmd {
+        return config(cmd, cfg_file, opts.allow_absolute_paths).await;
+    }
+
+    if let Some(Command::Checksum(ChecksumCommand::Compute { algorithm, files })) = opts.cmd {
+        for file in files {
+            let hash = file_hash(&file, algorithm).await?;
+            println!("{}  {}", hash, file.display());
+        }
+        return Ok(());
     }
 
-    let ctx_path = &opts.context;
+    let local_ctx_path = workdir.join(LOCAL_CONTEXT_FILE);
+    let use_local_ctx = opts.local_context || local_ctx_path.exists();
+    let ctx_path = &if use_local_ctx {
+        local_ctx_path
+    } else {
+        opts.context.clone()
+    };
+    if opts.reset_context {
+        if let Err(err) = Context::list_contexts(ctx_path).await {
+            tracing::warn!("{}. Resetting it to an empty context file", err);
+            Context::reset_file(ctx_path).await?;
+        }
+    }
     let ctx_fn = |path| async move { Context::try_new(path, None).await };
     if let Some(Command::Context(cmd)) = opts.cmd {
-        return context(cmd, ctx_path.as_path(), ctx_fn).await;
+        let global_path = Some(opts.context.as_path()).filter(|_| use_local_ctx);
+        return context(cmd, ctx_path.as_path(), global_path, ctx_fn, &opts.http).await;
+    }
+
+    if let Some(Command::FetchArtifact {
+        group,
+        artifact,
+        version,
+        output,
+    }) = opts.cmd
+    {
+        let ctx = load_context(ctx_path, &opts.context, use_local_ctx).await?;
+        let client_v2 = http_client(&opts.http, &ctx)?.v2();
+        let output = output.map(|output| workdir.join(output));
+        return fetch_artifact(&client_v2, &group, &artifact, version, output, &ctx.auth).await;
+    }
+
+    if let Some(Command::PushArtifact {
+        group,
+        artifact,
+        file,
+        artifact_type,
+        name,
+        description,
+        version,
+    }) = opts.cmd
+    {
+        let ctx = load_context(ctx_path, &opts.context, use_local_ctx).await?;
+        let client_v2 = http_client(&opts.http, &ctx)?.v2();
+        let metadata = PushArtifactMetadata {
+            group_id: group,
+            artifact_id: artifact,
+            name,
+            description,
+            artifact_type,
+            version,
+            bump_version: false,
+            labels: None,
+            properties: None,
+        };
+        let file = file.map(|file| workdir.join(file));
+        return push_artifact(&client_v2, metadata, file, &ctx.auth).await;
     }
 
-    let ctx = ctx_fn(ctx_path).await?;
+    let state_change = match &opts.cmd {
+        Some(Command::DeprecateVersion(args)) => Some((args, ArtifactState::Deprecated)),
+        Some(Command::EnableVersion(args)) => Some((args, ArtifactState::Enabled)),
+        Some(Command::DisableVersion(args)) => Some((args, ArtifactState::Disabled)),
+        _ => None,
+    };
+    if let Some((args, state)) = state_change {
+        let ctx = load_context(ctx_path, &opts.context, use_local_ctx).await?;
+        let client_v2 = http_client(&opts.http, &ctx)?.v2();
+        client_v2
+            .set_artifact_version_state(
+                &args.group,
+                &args.artifact,
+                &args.version,
+                state,
+                &ctx.auth,
+            )
+            .await?;
+        tracing::info!(
+            "Set the state of {}/{} version {} to {}",
+            args.group,
+            args.artifact,
+            args.version,
+            state
+        );
+        return Ok(());
+    }
+
+    let mut config = load_config(cfg_file, opts.profile.as_deref()).await?;
+    for extra in &opts.extra_configs {
+        let extra = Config::load_from_file(workdir.join(extra), None).await?;
+        config = Config::merge(config, extra);
+    }
+    config.validate(opts.allow_absolute_paths)?;
+    if let Some(Command::Lockfile(cmd)) = opts.cmd {
+        return lockfile(cmd, &config).await;
+    }
+
+    let ctx = load_context(ctx_path, &opts.context, use_local_ctx).await?;
     let auth = ctx.auth.clone();
-    let config = Config::load_from_file(cfg_file).await?;
-    let client_v2 = Client::new(ctx.registry_url.clone()).v2();
-    let mut lockfile = LockFile::try_load_for_config(&config, &client_v2, &auth).await?;
-    let plan = Plan::new(ctx)
+    let client = http_client(&opts.http, &ctx)?;
+    let client_v2 = Arc::new(client.v2());
+    let mut lockfile = LockFile::try_load_for_config(&config, client_v2.as_ref(), &auth).await?;
+    let plan = Plan::new(ctx, client_v2.clone())
         .merge_with_config(&config)
         .merge_with_lockfile(&lockfile);
-    match opts.cmd.as_ref().unwrap_or(&Command::Sync {}) {
-        Command::Update => update(&client_v2, &config, &mut lockfile, &auth).await,
-        Command::Sync => sync(&client_v2, &plan, &workdir, &auth).await,
-        Command::Info => info(&client_v2, &auth).await,
+    plan.log_counts();
+    match cmd {
+        Command::Update { verbose } => {
+            let res = update(
+                client_v2.as_ref(),
+                &config,
+                &mut lockfile,
+                !opts.no_warn_on_outdated_pins,
+                *verbose,
+                &auth,
+            )
+            .await;
+            notification::notify(&config, &res, None).await;
+            res
+        }
+        Command::Sync { since, format } => {
+            let res = sync(
+                &plan,
+                &workdir,
+                &auth,
+                *since,
+                &SyncContext::new(
+                    opts.continue_on_error,
+                    opts.force_push,
+                    opts.max_concurrent_pushes,
+                    opts.max_concurrent_pulls,
+                    client,
+                ),
+                opts.validate_content,
+                opts.atomic_writes,
+                &mut lockfile,
+                *format,
+            )
+            .await;
+            let (pulled, pushed) = res.as_ref().copied().unwrap_or_default();
+            let res = res.and_then(|(_, pushed)| {
+                if pushed.failed > 0 {
+                    return Err(Error::setup(format!(
+                        "{} of {} push artifacts failed",
+                        pushed.failed,
+                        plan.push.len()
+                    )));
+                }
+                tracing::info!("Sync completed");
+                Ok(())
+            });
+            notification::notify(&config, &res, Some(pulled.merge(pushed))).await;
+            res
+        }
+        Command::Plan { format } => print_plan(&plan, *format),
+        Command::Info { format } => info(client_v2.as_ref(), &plan.ctx, &opts.http, *format).await,
+        Command::CheckUnused { delete_orphans } => {
+            check_unused(client_v2.as_ref(), &config, *delete_orphans, &auth).await
+        }
+        Command::Group(cmd) => group(client_v2.as_ref(), cmd, &auth).await,
+        Command::Lockfile(_) =>
+        /* We already run Lockfile */
+        {
+            Ok(())
+        }
+        Command::Config(_) =>
+        /* We already run Config */
+        {
+            Ok(())
+        }
+        Command::Checksum(_) =>
+        /* We already run Checksum */
+        {
+            Ok(())
+        }
         Command::Context(_) =>
         /* We already run Context */
         {
             Ok(())
         }
-        Command::Init =>
+        Command::Init { .. } =>
         /* we already run Init */
         {
             Ok(())
         }
+        Command::FetchArtifact { .. } =>
+        /* We already run FetchArtifact */
+        {
+            Ok(())
+        }
+        Command::PushArtifact { .. } =>
+        /* We already run PushArtifact */
+        {
+            Ok(())
+        }
+        Command::DeprecateVersion(_) | Command::EnableVersion(_) | Command::DisableVersion(_) =>
+        /* We already run the version state commands */
+        {
+            Ok(())
+        }
+    }
+}
+
+/// Warns about environments where writing the lockfile, artifacts or contexts will likely fail
+/// or be lost, like containers running as root without volumes. Only heuristics, never fatal.
+fn startup_checks(workdir: &Path) {
+    if std::env::var_os("HOME").as_deref() == Some(OsStr::new("/root"))
+        && !is_writable(Path::new(CONFIG_DIR.as_str()))
+    {
+        tracing::warn!(
+            "Running as root and the config directory {} is not writable, so contexts cannot be saved. \
+             If running in a container, mount a volume there or pass --context-file",
+            CONFIG_DIR.as_str()
+        );
+    }
+    if Path::new("/.dockerenv").exists() && !is_on_mounted_volume(workdir) {
+        tracing::warn!(
+            "Running in a container and {} is not on a mounted volume, so pulled artifacts \
+             and the lockfile are lost when the container exits. Mount the project directory \
+             with e.g. `docker run -v $PWD:/workspace -w /workspace`",
+            workdir.display()
+        );
+    }
+}
+
+/// Checks that a file can be created in `dir`, or in its closest existing parent
+/// if it does not exist yet.
+fn is_writable(dir: &Path) -> bool {
+    let dir = match dir.ancestors().find(|dir| dir.exists()) {
+        Some(dir) => dir,
+        None => return false,
+    };
+    let probe = dir.join(format!(".{}-write-check", env!("CARGO_BIN_NAME")));
+    match std::fs::File::create(&probe) {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Whether `path` lives on a mount other than the root filesystem, according to /proc/self/mountinfo.
+/// Assumes it does when mounts cannot be inspected.
+fn is_on_mounted_volume(path: &Path) -> bool {
+    let mountinfo = match std::fs::read_to_string("/proc/self/mountinfo") {
+        Ok(mountinfo) => mountinfo,
+        Err(_) => return true,
+    };
+    // The fifth field is the mount point
+    mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(Path::new)
+        .filter(|mount_point| path.starts_with(mount_point))
+        .any(|mount_point| mount_point != Path::new("/"))
+}
+
+/// Moves the process to `cwd`, if set, so that every relative path is resolved against it,
+/// and returns the resulting working directory.
+fn enter_workdir(cwd: Option<&Path>) -> Result<PathBuf, Error> {
+    if let Some(cwd) = cwd {
+        std::env::set_current_dir(cwd).map_err(|err| {
+            Error::setup(format!(
+                "Cannot use {} as working directory: {}",
+                cwd.display(),
+                err
+            ))
+        })?;
+    }
+    Ok(std::env::current_dir()?)
+}
+
+/// Loads the config file, explaining how to create it when it does not exist.
+async fn load_config(cfg_file: PathBuf, profile: Option<&str>) -> Result<Config, Error> {
+    match Config::load_from_file(cfg_file.clone(), profile).await {
+        Err(Error::Io(err)) if err.kind() == ErrorKind::NotFound => Err(Error::setup(format!(
+            "Config file {} not found. Run 'apicurio-sync init' to create one, or specify --config-file to use a different path.",
+            cfg_file.display()
+        ))),
+        res => res,
     }
 }
 
@@ -206,37 +1007,237 @@ async fn update(
     provider: &impl Provider,
     config: &Config,
     lockfile: &mut LockFile,
+    warn_on_outdated_pins: bool,
+    verbose: bool,
     auth: &context::Auth,
 ) -> Result<(), Error> {
     tracing::info!("Updating lockfile with remote registry");
-    lockfile.update(config, provider, auth).await?;
+    let mut changes = lockfile
+        .update_with_changes(config, provider, warn_on_outdated_pins, auth)
+        .await?;
+    changes.retain(|change| verbose || change.changed);
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    if changes.is_empty() {
+        tracing::info!("All artifacts are up to date");
+    } else {
+        println!("{:<40} {:<10}    NEW", "PATH", "OLD");
+        for change in changes {
+            println!(
+                "{:<40} {:<10} -> {}",
+                change.path.display(),
+                change.old_version.as_deref().unwrap_or("-"),
+                change.new_version
+            );
+        }
+    }
     tracing::info!("Lockfile update completed. Rerun sync to update the artifacts");
     Ok(())
 }
 
 async fn init(
     cfg_file: PathBuf,
+    force: bool,
+    template: Template,
     provider: &impl Provider,
     auth: &context::Auth,
 ) -> Result<(), Error> {
-    let config = Config::write_empty(cfg_file).await?;
-    LockFile::try_load_for_config(&config, provider, auth).await?;
+    let config = Config::write_empty(cfg_file, force, template).await?;
+    tracing::info!("Created {}", config.path.display());
+    let lockfile = LockFile::try_load_for_config(&config, provider, auth).await?;
+    tracing::info!("Created {}", lockfile.path().display());
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn sync(
-    provider: &impl Provider,
     plan: &Plan,
     workdir: &Path,
     auth: &context::Auth,
-) -> Result<(), Error> {
+    since: Option<DateTime<Utc>>,
+    sync_ctx: &SyncContext,
+    validate_content: bool,
+    atomic_writes: bool,
+    lockfile: &mut LockFile,
+    format: Format,
+) -> Result<(SyncReport, SyncReport), Error> {
+    if !plan.unresolved.is_empty() {
+        let paths: Vec<String> = plan
+            .unresolved
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        return Err(Error::setup(format!(
+            "Run 'update' first: the following artifacts have no locked version: {}",
+            paths.join(", ")
+        )));
+    }
+
+    let errors = plan.validate();
+    if !errors.is_empty() {
+        let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        return Err(Error::setup(format!(
+            "Invalid sync plan:\n  {}",
+            errors.join("\n  ")
+        )));
+    }
+
     tracing::info!("Syncing artifacts with remote registry");
-    sync::pull_artifacts(provider, plan, workdir, auth).await?;
-    sync::push_artifacts(provider, plan, workdir, auth).await?;
-    tracing::info!("Sync completed");
+    let started = Instant::now();
+    let pulled = sync::pull_artifacts(
+        plan,
+        workdir,
+        auth,
+        since,
+        sync_ctx,
+        validate_content,
+        atomic_writes,
+        lockfile,
+    )
+    .await?;
+    let mut pushed = SyncReport::default();
+    let res = sync::push_artifacts(plan, workdir, auth, sync_ctx, lockfile, &mut pushed).await;
+    // Also print what was pushed when some pushes failed
+    print_sync_report(pulled, pushed, started.elapsed(), format)?;
+    res.map(|_| (pulled, pushed))
+}
+
+fn print_sync_report(
+    pulled: SyncReport,
+    pushed: SyncReport,
+    elapsed: Duration,
+    format: Format,
+) -> Result<(), Error> {
+    if format == Format::Json {
+        let summary = serde_json::json!({
+            "pulled": pulled.pulled,
+            "pushed": pushed.pushed,
+            "skipped": pulled.skipped + pushed.skipped,
+            "failed": pushed.failed,
+            "duration_ms": elapsed.as_millis() as u64,
+        });
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+        return Ok(());
+    }
+
+    println!("{:<8} {:>6} {:>8} {:>7}", "", "DONE", "SKIPPED", "FAILED");
+    println!(
+        "{:<8} {:>6} {:>8} {:>7}",
+        "Pulled", pulled.pulled, pulled.skipped, "-"
+    );
+    println!(
+        "{:<8} {:>6} {:>8} {:>7}",
+        "Pushed", pushed.pushed, pushed.skipped, pushed.failed
+    );
+    println!("Completed in {:.2}s", elapsed.as_secs_f64());
     Ok(())
 }
 
+async fn config(
+    cmd: ConfigCommand,
+    cfg_file: PathBuf,
+    allow_absolute_paths: bool,
+) -> Result<(), Error> {
+    match cmd {
+        ConfigCommand::Validate { strict } => {
+            if strict {
+                Config::validate_schema(&cfg_file).await?;
+            }
+            let config = Config::load_from_file(cfg_file, None).await?;
+            config.validate(allow_absolute_paths)?;
+            tracing::info!("Config file {} is valid", config.path.display());
+            Ok(())
+        }
+        ConfigCommand::AddPull {
+            group,
+            artifact,
+            version,
+            path,
+        } => {
+            let mut document = Document::load(cfg_file).await?;
+            document.append(
+                "pull",
+                &config::PullArtifactRef {
+                    group,
+                    artifact,
+                    version,
+                    version_expression: None,
+                    path,
+                    rename: None,
+                    output_format: None,
+                    pull_references: false,
+                    on_pull: None,
+                    strip_prefix: None,
+                    add_prefix: None,
+                },
+            )?;
+            document.save().await?;
+            tracing::info!(
+                "Updated {}. Run `update` to lock the new artifact",
+                document.path().display()
+            );
+            Ok(())
+        }
+    }
+}
+
+fn print_plan(plan: &Plan, format: Format) -> Result<(), Error> {
+    let entries = plan.describe();
+    if format == Format::Json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+
+    println!(
+        "{:<40} {:<5} {:<20} {:<30} {:<10} SOURCE",
+        "PATH", "DIR", "GROUP", "ARTIFACT", "VERSION"
+    );
+    for entry in entries {
+        println!(
+            "{:<40} {:<5} {:<20} {:<30} {:<10} {}",
+            entry.path.display(),
+            entry.direction,
+            entry.group.as_deref().unwrap_or("-"),
+            entry.artifact.as_deref().unwrap_or("-"),
+            entry.version.as_deref().unwrap_or("-"),
+            entry
+                .version_source
+                .map(|source| source.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+        );
+    }
+    Ok(())
+}
+
+async fn lockfile(cmd: LockfileCommand, config: &Config) -> Result<(), Error> {
+    match cmd {
+        LockfileCommand::Prune => {
+            let mut lockfile = LockFile::load_for_config(config).await?;
+            let pruned = lockfile.prune(config);
+            lockfile.save().await?;
+            for path in &pruned {
+                tracing::info!("Pruned {}", path.display());
+            }
+            tracing::info!("Pruned {} lockfile entries", pruned.len());
+            Ok(())
+        }
+        LockfileCommand::Clean { confirm } => {
+            if !confirm {
+                return Err(Error::setup(
+                    "Cleaning the lockfile removes every entry, pass --confirm to proceed",
+                ));
+            }
+            let mut lockfile = LockFile::load_for_config(config).await?;
+            let removed = lockfile.pull.len() + lockfile.push.len();
+            lockfile.clean().await?;
+            tracing::info!(
+                "Removed {} lockfile entries. Run `update` to lock the artifacts again",
+                removed
+            );
+            Ok(())
+        }
+    }
+}
+
 async fn context<
     P: AsRef<Path>,
     Fut: Future<Output = Result<Context, Error>>,
@@ -244,16 +1245,27 @@ async fn context<
 >(
     cmd: ContextCommand,
     ctx_path: P,
+    global_path: Option<&Path>,
     load_ctx: Fun,
+    http: &HttpOpts,
 ) -> Result<(), Error> {
     match cmd {
-        ContextCommand::Current => {
+        ContextCommand::Current { format } => {
             let ctx = load_ctx(ctx_path).await?;
-            tracing::info!("{}", ctx.context_name);
+            if format == Format::Json {
+                let current = serde_json::json!({
+                    "context_name": ctx.context_name,
+                    "registry_url": ctx.registry_url,
+                    "auth_type": auth_type(&ctx),
+                });
+                println!("{}", serde_json::to_string_pretty(&current)?);
+            } else {
+                println!("{}", ctx.context_name);
+            }
             Ok(())
         }
-        ContextCommand::Init => {
-            Context::write_empty_file(ctx_path.as_ref()).await?;
+        ContextCommand::Init { force } => {
+            Context::write_empty_file(ctx_path.as_ref(), force).await?;
             tracing::info!("Initialzed empty context file");
             Ok(())
         }
@@ -261,8 +1273,22 @@ async fn context<
             context_name,
             url,
             current,
+            remove_auth,
+            auth_helper,
+            validate,
+            headers,
+            remove_headers,
+            set_default_url,
         } => {
             let path = ctx_path.as_ref();
+            if let Some(url) = set_default_url {
+                Context::write_default_url(path, url.clone()).await?;
+                tracing::info!("Set default registry URL to {}", url);
+            }
+            let context_name = match context_name {
+                Some(context_name) => context_name,
+                None => return Ok(()),
+            };
             let mut ctx = Context::from_file(path, Some(context_name.clone()))
                 .await?
                 .or_else(|| {
@@ -273,22 +1299,142 @@ async fn context<
             if let Some(url) = url {
                 ctx.registry_url = url;
             }
-            ctx.write(path, current).await?;
+            let credentials_changed = remove_auth || auth_helper.is_some();
+            if remove_auth {
+                ctx.set_auth(context::Auth::None);
+            }
+            if let Some(helper) = auth_helper {
+                ctx.auth_helper = Some(helper).filter(|helper| !helper.is_empty());
+            }
+            for name in remove_headers {
+                ctx.extra_headers.remove(&name);
+            }
+            ctx.extra_headers.extend(headers);
+            if validate {
+                let checked = with_credentials(ctx.clone()).await?;
+                let client = http_client(http, &checked)?.v2();
+                client.system_info(&checked.auth).await.map_err(|err| {
+                    Error::setup(format!(
+                        "Registry {} is not reachable, context not saved: {}",
+                        checked.registry_url, err
+                    ))
+                })?;
+            }
+            match global_path {
+                Some(global_path) => {
+                    ctx.write_without_credentials(path, current).await?;
+                    if credentials_changed {
+                        ctx.write_credentials(global_path).await?;
+                    }
+                }
+                None => ctx.write(path, current).await?,
+            }
             tracing::info!("Updated context {}", context_name);
+            if remove_auth {
+                tracing::info!("Cleared authentication for context '{}'", context_name);
+            }
+            Ok(())
+        }
+        ContextCommand::Logout { context_name } => {
+            let path = ctx_path.as_ref();
+            let mut ctx = Context::from_file(path, context_name)
+                .await?
+                .ok_or_else(|| Error::setup("No such context configured!"))?;
+            ctx.set_auth(context::Auth::None);
+            save_context(&ctx, path, global_path, false).await?;
+            tracing::info!("Cleared authentication for context '{}'", ctx.context_name);
             Ok(())
         }
         ContextCommand::Show => {
-            let mut file = File::open(ctx_path.as_ref()).await?;
-            let mut buf = String::new();
-            file.read_to_string(&mut buf).await?;
-            println!("{}", buf);
+            let (contexts, current) = Context::list_contexts(ctx_path.as_ref()).await?;
+            for ctx in contexts {
+                let marker = if Some(&ctx.context_name) == current.as_ref() {
+                    " (current)"
+                } else {
+                    ""
+                };
+                println!("{}{}", ctx.context_name, marker);
+                println!("  url: {}", ctx.registry_url);
+                println!("  auth: {}", ctx.auth);
+                if let Some(helper) = &ctx.auth_helper {
+                    println!("  auth helper: {}", helper);
+                }
+                // Header values may carry credentials too
+                let mut headers: Vec<&str> = ctx.extra_headers.keys().map(String::as_str).collect();
+                if !headers.is_empty() {
+                    headers.sort_unstable();
+                    println!("  headers: {}", headers.join(", "));
+                }
+            }
             Ok(())
         }
-        ContextCommand::Login(cmd) => login(cmd, ctx_path).await,
+        ContextCommand::List { active_only } => {
+            let (contexts, current) = Context::list_contexts(ctx_path.as_ref()).await?;
+            let contexts: Vec<&Context> = if active_only {
+                let active: Vec<&Context> = contexts
+                    .iter()
+                    .filter(|ctx| Some(&ctx.context_name) == current.as_ref())
+                    .collect();
+                if active.is_empty() {
+                    return Err(Error::setup("No current context set"));
+                }
+                active
+            } else {
+                contexts.iter().collect()
+            };
+            print_contexts(&contexts, current.as_deref());
+            Ok(())
+        }
+        ContextCommand::Login { use_keychain, cmd } => {
+            login(cmd, ctx_path, global_path, http, use_keychain).await
+        }
+    }
+}
+
+fn print_contexts(contexts: &[&Context], current: Option<&str>) {
+    println!(
+        "{:<1} {:<20} {:<40} {:<8} EXPIRES",
+        "", "NAME", "URL", "AUTH"
+    );
+    for ctx in contexts {
+        let expires = match &ctx.auth {
+            context::Auth::Oidc { expires_at, .. } if ctx.auth_helper.is_none() => {
+                expires_at.to_rfc3339()
+            }
+            _ => "-".to_string(),
+        };
+        let marker = if Some(ctx.context_name.as_str()) == current {
+            "*"
+        } else {
+            ""
+        };
+        println!(
+            "{:<1} {:<20} {:<40} {:<8} {}",
+            marker,
+            ctx.context_name,
+            ctx.registry_url,
+            auth_type(ctx),
+            expires
+        );
+    }
+}
+
+/// How the context authenticates, as shown to users.
+fn auth_type(ctx: &Context) -> &'static str {
+    if ctx.auth_helper.is_some() {
+        "helper"
+    } else {
+        ctx.auth.kind()
     }
 }
 
-async fn login<P: AsRef<Path>>(cmd: LoginCommand, ctx_path: P) -> Result<(), Error> {
+async fn login<P: AsRef<Path>>(
+    cmd: LoginCommand,
+    ctx_path: P,
+    global_path: Option<&Path>,
+    http: &HttpOpts,
+    use_keychain: bool,
+) -> Result<(), Error> {
     let path = ctx_path.as_ref();
     let ctx = Context::from_file(path, None)
         .await?
@@ -301,7 +1447,18 @@ async fn login<P: AsRef<Path>>(cmd: LoginCommand, ctx_path: P) -> Result<(), Err
             client_secret,
             scope,
             port,
-        } => Box::new(OidcProvider::new(issuer_url, client_id, client_secret, scope, port).await?),
+            login_timeout_secs,
+        } => Box::new(
+            OidcProvider::new(
+                issuer_url,
+                client_id,
+                client_secret,
+                scope,
+                port,
+                Duration::from_secs(login_timeout_secs),
+            )
+            .await?,
+        ),
         LoginCommand::Basic {
             username,
             password_stdin,
@@ -316,23 +1473,448 @@ async fn login<P: AsRef<Path>>(cmd: LoginCommand, ctx_path: P) -> Result<(), Err
             };
             Box::new(BasicAuthProvider::new(username, password))
         }
+        LoginCommand::ApikeyQuery { key } => Box::new(ApiKeyQueryProvider::new(key)),
     };
 
-    let ctx = provider.login(ctx).await?;
-    ctx.write(path, true).await?;
+    let mut ctx = provider.login(ctx).await?;
+    // Never replace working credentials with ones the registry rejects
+    let client = http_client(http, &ctx)?.v2();
+    if let Err(err) = client.system_info(&ctx.auth).await {
+        return Err(Error::Auth(
+            format!("Login produced invalid credentials: {}", err).into(),
+        ));
+    }
+    if use_keychain {
+        ctx.store_secrets();
+    }
+    save_context(&ctx, path, global_path, true).await?;
     tracing::info!("Updated context auth information");
     Ok(())
 }
 
-async fn info(provider: &impl Provider, auth: &context::Auth) -> Result<(), Error> {
-    let info = provider.system_info(auth).await?;
-    tracing::info!("{:?}", info);
+/// Saves the context to `path`. Project local contexts, for which `global_path` is set, are saved
+/// without credentials, which go to the context with the same name in the global file instead.
+async fn save_context(
+    ctx: &Context,
+    path: &Path,
+    global_path: Option<&Path>,
+    current: bool,
+) -> Result<(), Error> {
+    match global_path {
+        Some(global_path) => {
+            ctx.write_without_credentials(path, current).await?;
+            ctx.write_credentials(global_path).await
+        }
+        None => ctx.write(path, current).await,
+    }
+}
+
+fn http_client(opts: &HttpOpts, ctx: &Context) -> Result<Client, Error> {
+    let mut builder = Client::builder(ctx.registry_url.clone())
+        .extra_headers(ctx.extra_headers.clone())
+        .retry(opts.retries)
+        .no_verify_tls(opts.insecure)
+        .pool_max_idle_per_host(opts.pool_max_idle)
+        .large_artifact_threshold_bytes(opts.large_artifact_threshold_bytes);
+    if let Some(secs) = opts.pool_idle_timeout_secs {
+        builder = builder.pool_idle_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = opts.http_timeout_secs {
+        builder = builder.timeout(Duration::from_secs(secs));
+    }
+    if let Some(ca_cert) = &opts.ca_cert {
+        builder = builder.ca_cert(ca_cert.clone());
+    }
+    if let Some(proxy) = &opts.proxy {
+        builder = builder.proxy(proxy.clone());
+    }
+    builder.build()
+}
+
+/// Loads the context from `path`. Local contexts without credentials borrow them from the
+/// context with the same name in the global file at `global_path`. Contexts with an auth helper
+/// get their credentials from it instead.
+async fn load_context(path: &Path, global_path: &Path, local: bool) -> Result<Context, Error> {
+    let (ctx, reasons) = Context::try_new_verbose(path, None).await;
+    let mut ctx = match ctx {
+        Some(ctx) => {
+            for reason in &reasons {
+                tracing::debug!("{}", reason);
+            }
+            ctx
+        }
+        None => {
+            for reason in &reasons {
+                tracing::warn!("{}", reason);
+            }
+            return Err(Error::setup(
+                "Failed to read context from either file or env",
+            ));
+        }
+    };
+    let mut auth_path = path;
+    if local && ctx.auth_helper.is_none() && matches!(ctx.auth, context::Auth::None) {
+        let name = Some(ctx.context_name.clone());
+        if let Some(global) = Context::from_file(global_path, name).await? {
+            ctx.set_auth(global.auth);
+            ctx.auth_helper = global.auth_helper;
+            auth_path = global_path;
+        }
+    }
+    with_credentials(ctx.resolve_auth(auth_path).await?).await
+}
+
+/// Fills in the actual credentials of `ctx`, from its auth helper or the keychain.
+async fn with_credentials(ctx: Context) -> Result<Context, Error> {
+    match ctx.auth_helper.clone() {
+        Some(helper) => HelperProvider::new(helper).login(ctx).await,
+        None => ctx.resolve_secrets(),
+    }
+}
+
+async fn fetch_artifact(
+    provider: &impl Provider,
+    group: &str,
+    artifact: &str,
+    version: Option<String>,
+    output: Option<PathBuf>,
+    auth: &context::Auth,
+) -> Result<(), Error> {
+    let (version, content) = match version {
+        Some(version) if version != "latest" => {
+            provider
+                .fetch_artifact_by_version_expression(group, artifact, &version, auth)
+                .await?
+        }
+        _ => {
+            let version = provider
+                .fetch_artifact_metadata(group, artifact, auth)
+                .await?
+                .version;
+            let content = provider
+                .fetch_artifact_version(group, artifact, &version, auth)
+                .await?;
+            (version, content)
+        }
+    };
+    match output {
+        Some(output) => {
+            sync::create_parent_dir(&output).await?;
+            tokio::fs::write(&output, content).await?;
+            tracing::info!(
+                "Fetched {}/{} version {} into {}",
+                group,
+                artifact,
+                version,
+                output.display()
+            );
+        }
+        None => std::io::stdout().write_all(&content)?,
+    }
+    Ok(())
+}
+
+async fn push_artifact(
+    provider: &impl Provider,
+    metadata: PushArtifactMetadata,
+    file: Option<PathBuf>,
+    auth: &context::Auth,
+) -> Result<(), Error> {
+    let content = match file {
+        Some(file) => {
+            let file = tokio::fs::File::open(file).await?;
+            PushContent {
+                len: file.metadata().await?.len(),
+                reader: Box::new(file),
+            }
+        }
+        None => {
+            let mut content = Vec::new();
+            std::io::stdin().read_to_end(&mut content)?;
+            PushContent::from_bytes(content)
+        }
+    };
+    let pushed = provider.push_artifact(metadata, content, auth).await?;
+    println!(
+        "Pushed {}/{} version {} (global ID {})",
+        pushed.group_id, pushed.id, pushed.version, pushed.global_id
+    );
     Ok(())
 }
 
+/// Prints how the registry is reached and what it reports about itself, to diagnose connection problems.
+async fn info(
+    provider: &impl Provider,
+    ctx: &Context,
+    http: &HttpOpts,
+    format: Format,
+) -> Result<(), Error> {
+    let expires_at = match &ctx.auth {
+        context::Auth::Oidc { expires_at, .. } => Some(expires_at),
+        _ => None,
+    };
+    if format == Format::Json {
+        let system = provider.system_info(&ctx.auth).await?;
+        let info = serde_json::json!({
+            "registry_url": ctx.registry_url,
+            "api_version": "v2",
+            "auth_type": auth_type(ctx),
+            "auth": ctx.auth.to_string(),
+            "token_expires_at": expires_at,
+            "tls_verify": !http.insecure,
+            "proxy": http.proxy,
+            "system": system,
+        });
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+
+    // Printed before contacting the registry, so they are shown even if it is unreachable
+    println!("{:<14} {}", "REGISTRY", ctx.registry_url);
+    println!("{:<14} v2", "API VERSION");
+    match &ctx.auth_helper {
+        Some(helper) => println!("{:<14} {} (helper: {})", "AUTH", ctx.auth, helper),
+        None => println!("{:<14} {}", "AUTH", ctx.auth),
+    }
+    if let Some(expires_at) = expires_at {
+        let status = if *expires_at <= Utc::now() {
+            "expired"
+        } else {
+            "valid"
+        };
+        println!(
+            "{:<14} {} ({})",
+            "TOKEN EXPIRY",
+            expires_at.to_rfc3339(),
+            status
+        );
+    }
+    println!(
+        "{:<14} {}",
+        "TLS VERIFY",
+        if http.insecure { "disabled" } else { "enabled" }
+    );
+    if let Some(proxy) = &http.proxy {
+        println!("{:<14} {}", "PROXY", proxy);
+    }
+
+    let system = provider.system_info(&ctx.auth).await?;
+    println!("{:<14} {}", "NAME", system.name);
+    println!("{:<14} {}", "DESCRIPTION", system.description);
+    println!("{:<14} {}", "VERSION", system.version);
+    println!("{:<14} {}", "BUILT ON", system.built_on);
+    Ok(())
+}
+
+async fn check_unused(
+    provider: &impl Provider,
+    config: &Config,
+    delete_orphans: bool,
+    auth: &context::Auth,
+) -> Result<(), Error> {
+    let groups: BTreeSet<&str> = config.push.iter().map(|a| a.group.as_str()).collect();
+    let mut remote = BTreeSet::new();
+    for group in groups {
+        let artifacts = provider.fetch_all_artifact_metadata(group, auth).await?;
+        remote.extend(
+            artifacts
+                .into_iter()
+                .map(|artifact| (artifact.group_id, artifact.id)),
+        );
+    }
+
+    let local: BTreeSet<(String, String)> = config
+        .push
+        .iter()
+        .map(|a| (a.group.clone(), a.artifact.clone()))
+        .collect();
+    let missing: Vec<_> = local.difference(&remote).collect();
+    let orphans: Vec<_> = remote.difference(&local).collect();
+
+    if missing.is_empty() {
+        tracing::info!("All push artifacts are present in the registry");
+    } else {
+        tracing::info!("Push artifacts missing from the registry (will be created on next sync):");
+        for (group, artifact) in &missing {
+            tracing::info!("  {}/{}", group, artifact);
+        }
+    }
+
+    if orphans.is_empty() {
+        tracing::info!("No orphaned artifacts found in the registry");
+        return Ok(());
+    }
+
+    tracing::info!("Registry artifacts not present in config (potential orphans):");
+    for (group, artifact) in &orphans {
+        tracing::info!("  {}/{}", group, artifact);
+    }
+
+    if !delete_orphans {
+        return Ok(());
+    }
+
+    print!("Delete {} orphaned artifacts? [y/N] ", orphans.len());
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    if !answer.trim().eq_ignore_ascii_case("y") {
+        tracing::info!("Aborted, no artifacts deleted");
+        return Ok(());
+    }
+
+    for (group, artifact) in orphans {
+        provider.delete_artifact(group, artifact, auth).await?;
+        tracing::info!("Deleted {}/{}", group, artifact);
+    }
+    Ok(())
+}
+
+async fn group(
+    provider: &impl Provider,
+    cmd: &GroupCommand,
+    auth: &context::Auth,
+) -> Result<(), Error> {
+    match cmd {
+        GroupCommand::Info { group, format } => {
+            let metadata = provider.fetch_group_metadata(group, auth).await?;
+            if *format == Format::Json {
+                println!("{}", serde_json::to_string_pretty(&metadata)?);
+                return Ok(());
+            }
+            println!("{:<12} {}", "GROUP", metadata.group_id);
+            println!(
+                "{:<12} {}",
+                "DESCRIPTION",
+                metadata.description.as_deref().unwrap_or("-")
+            );
+            println!(
+                "{:<12} {} by {}",
+                "CREATED", metadata.created_on, metadata.created_by
+            );
+            println!(
+                "{:<12} {} by {}",
+                "MODIFIED", metadata.modified_on, metadata.modified_by
+            );
+            Ok(())
+        }
+        GroupCommand::Create { group, description } => {
+            let metadata = provider
+                .create_group(group, description.clone(), auth)
+                .await?;
+            tracing::info!("Created group {}", metadata.group_id);
+            Ok(())
+        }
+        GroupCommand::Delete { group } => {
+            print!("Delete group {} and all of its artifacts? [y/N] ", group);
+            std::io::stdout().flush()?;
+            let mut answer = String::new();
+            std::io::stdin().lock().read_line(&mut answer)?;
+            if !answer.trim().eq_ignore_ascii_case("y") {
+                tracing::info!("Aborted, group {} not deleted", group);
+                return Ok(());
+            }
+            provider.delete_group(group, auth).await?;
+            tracing::info!("Deleted group {}", group);
+            Ok(())
+        }
+    }
+}
+
 #[tokio::main]
-async fn main() {
-    if let Err(err) = run().await {
-        tracing::info!("Error: {}", err)
+async fn main() -> ExitCode {
+    let opts: Opts = Opts::from_args();
+    let res = match opts.timeout_secs.filter(|secs| *secs > 0) {
+        Some(secs) => tokio::time::timeout(Duration::from_secs(secs), run(opts))
+            .await
+            .unwrap_or_else(|_| {
+                Err(Error::Timeout(format!(
+                    "total sync timeout after {}s",
+                    secs
+                )))
+            }),
+        None => run(opts).await,
+    };
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => err.report(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, system_info, temp_dir};
+
+    fn read_json(path: &Path) -> serde_json::Value {
+        serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn login_with_local_context_keeps_credentials_in_global_file() {
+        let url = test_util::serve(system_info());
+        let dir = temp_dir("login-local-context");
+        let local = dir.join(LOCAL_CONTEXT_FILE);
+        let global = dir.join("context.json");
+        Context::write_empty_file(&local, false).await.unwrap();
+        Context::new("dev".to_string(), url)
+            .write(&local, true)
+            .await
+            .unwrap();
+
+        login(
+            LoginCommand::Basic {
+                username: "bob".to_string(),
+                password_stdin: false,
+            },
+            &local,
+            Some(&global),
+            &HttpOpts::from_iter(&["apicurio-sync"]),
+            false,
+        )
+        .await
+        .expect("login");
+
+        let local = read_json(&local);
+        assert_eq!(local["contexts"]["dev"]["auth"]["type"], "none");
+        assert!(local["contexts"]["dev"].get("auth_helper").is_none());
+        let global = read_json(&global);
+        assert_eq!(global["contexts"]["dev"]["auth"]["type"], "basic");
+        assert_eq!(global["contexts"]["dev"]["auth"]["username"], "bob");
+    }
+
+    #[tokio::test]
+    async fn cwd_resolves_the_config_file_against_it() {
+        let dir = temp_dir("cwd-config-file");
+        std::fs::write(dir.join("apicurio-sync.yaml"), "pull: []\n").unwrap();
+        let opts = Opts::from_iter(&[
+            "apicurio-sync".as_ref(),
+            "--cwd".as_ref(),
+            dir.as_os_str(),
+            "plan".as_ref(),
+        ]);
+
+        let workdir = enter_workdir(opts.cwd.as_deref()).unwrap();
+        let cfg_file = workdir.join(&opts.config);
+
+        assert_eq!(
+            cfg_file.canonicalize().unwrap(),
+            dir.join("apicurio-sync.yaml").canonicalize().unwrap()
+        );
+        load_config(cfg_file, None).await.expect("load config");
+    }
+
+    #[tokio::test]
+    async fn missing_config_file_error_suggests_init() {
+        let cfg_file = temp_dir("missing-config-file").join("apicurio-sync.yaml");
+
+        match load_config(cfg_file.clone(), None).await {
+            Err(Error::Setup(msg)) => {
+                assert!(msg.contains(&cfg_file.display().to_string()), "{}", msg);
+                assert!(msg.contains("apicurio-sync init"), "{}", msg);
+            }
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("missing config file was loaded"),
+        }
     }
 }
@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::ClientBuilder;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::error::Error;
+use crate::sync::SyncReport;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    Success,
+    Failure,
+}
+
+#[derive(Debug, Serialize)]
+struct Payload {
+    status: Status,
+    timestamp: DateTime<Utc>,
+    /// Left out for operations that transfer no artifacts, like `update`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artifacts_pulled: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artifacts_pushed: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Posts the outcome of an operation to the webhook configured for it, if any, with how many
+/// artifacts it actually pulled and pushed according to `report`, even if it failed.
+/// Failing to deliver the notification is logged but never fails the operation itself.
+pub async fn notify(config: &Config, result: &Result<(), Error>, report: Option<SyncReport>) {
+    let artifacts_pulled = report.map(|report| report.pulled);
+    let artifacts_pushed = report.map(|report| report.pushed);
+    let (url, payload) = match result {
+        Ok(()) => (
+            &config.on_success_webhook,
+            Payload {
+                status: Status::Success,
+                timestamp: Utc::now(),
+                artifacts_pulled,
+                artifacts_pushed,
+                error: None,
+            },
+        ),
+        Err(err) => (
+            &config.on_failure_webhook,
+            Payload {
+                status: Status::Failure,
+                timestamp: Utc::now(),
+                artifacts_pulled,
+                artifacts_pushed,
+                error: Some(err.to_string()),
+            },
+        ),
+    };
+    let url = match url {
+        Some(url) => url,
+        None => return,
+    };
+
+    let client = ClientBuilder::new()
+        .use_rustls_tls()
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()
+        .expect("ClientBuilder::build");
+    let req = client.post(url.clone()).json(&payload);
+    tracing::debug!("{:?}", req);
+    if let Err(err) = req.send().await.and_then(|res| res.error_for_status()) {
+        tracing::warn!("Failed to notify webhook {}: {}", url, err);
+    }
+}
@@ -1,44 +1,66 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
+use url::Url;
 
-use crate::config::Config;
+use crate::config::{Config, OnConflict, OutputFormat};
 use crate::context::Context;
 use crate::lockfile::LockFile;
-use crate::provider::ArtifactType;
+use crate::provider::{ArtifactType, Provider};
 
+#[allow(dead_code)]
 pub struct Plan {
     pub push: HashMap<PathBuf, PushArtifactRef>,
     pub pull: HashMap<PathBuf, PullArtifactRef>,
     pub ctx: Context,
+    pub provider: Arc<dyn Provider>,
+    /// Pull artifacts with no version from either the config or the lockfile, sorted by path.
+    pub unresolved: Vec<PathBuf>,
 }
 
 impl Plan {
-    pub fn new(ctx: Context) -> Self {
+    pub fn new(ctx: Context, provider: Arc<dyn Provider>) -> Self {
         Self {
             push: HashMap::new(),
             pull: HashMap::new(),
             ctx,
+            provider,
+            unresolved: Vec::new(),
         }
     }
 
     pub fn merge_with_config(mut self, cfg: &Config) -> Self {
         for artifact in &cfg.pull {
-            let mut pull_ref = self
-                .pull
-                .entry(artifact.path.clone())
-                .or_insert_with(PullArtifactRef::default);
+            let pull_ref = self.pull.entry(artifact.path.clone()).or_default();
             pull_ref.group = Some(artifact.group.clone());
             pull_ref.artifact = Some(artifact.artifact.clone());
             pull_ref.version = artifact.version.clone();
+            pull_ref.version_source = artifact.version.as_ref().map(|_| VersionSource::Config);
+            pull_ref.rename = artifact.rename.clone();
+            pull_ref.output_format = artifact.output_format;
+            pull_ref.pull_references = artifact.pull_references;
+            pull_ref.on_pull = artifact.on_pull.clone();
+            pull_ref.strip_prefix = artifact.strip_prefix.clone();
+            pull_ref.add_prefix = artifact.add_prefix.clone();
         }
+        self.unresolved = self
+            .pull
+            .iter()
+            .filter(|(_, artifact)| artifact.version.is_none())
+            .map(|(path, _)| path.clone())
+            .collect();
+        self.unresolved.sort();
 
         for artifact in &cfg.push {
-            let mut push_ref = self
-                .push
-                .entry(artifact.path.clone())
-                .or_insert_with(PushArtifactRef::default);
+            let artifact = &match &cfg.global {
+                Some(global) => artifact.clone().with_global_defaults(global),
+                None => artifact.clone(),
+            };
+            let push_ref = self.push.entry(artifact.source()).or_default();
+            push_ref.url = artifact.url.clone();
             push_ref.group = Some(artifact.group.clone());
             push_ref.artifact = Some(artifact.artifact.clone());
             push_ref.artifact_type = artifact.artifact_type.clone();
@@ -46,33 +68,153 @@ impl Plan {
             push_ref.description = artifact.description.clone();
             push_ref.labels = artifact.labels.clone();
             push_ref.properties = artifact.properties.clone();
+            push_ref.bump_version = artifact.bump_version;
+            push_ref.depends_on = artifact.depends_on.clone();
+            push_ref.on_push = artifact.on_push.clone();
+            push_ref.on_conflict = artifact.on_conflict.unwrap_or_default();
         }
         self
     }
 
     pub fn merge_with_lockfile(mut self, lockfile: &LockFile) -> Self {
         for (path, artifact) in &lockfile.pull {
-            let mut pull_ref = self
-                .pull
-                .entry(path.clone())
-                .or_insert_with(PullArtifactRef::default);
+            let pull_ref = self.pull.entry(path.clone()).or_default();
             pull_ref.group = Some(artifact.group.clone());
             pull_ref.artifact = Some(artifact.artifact.clone());
+            if pull_ref.version.as_ref() != Some(&artifact.version) {
+                pull_ref.version_source = Some(VersionSource::Lockfile);
+            }
             pull_ref.version = Some(artifact.version.clone());
+            // Lockfiles written before types were recorded have none, leave it to sync to fetch it
+            if artifact.artifact_type.is_some() {
+                pull_ref.artifact_type = artifact.artifact_type.clone();
+            }
+            pull_ref.modified_on = artifact.modified_on.clone();
+            pull_ref.content_hash = artifact.content_hash.clone();
         }
+        self.unresolved
+            .retain(|path| !lockfile.pull.contains_key(path));
         self
     }
+
+    pub fn counts(&self) -> PlanCounts {
+        PlanCounts {
+            pull: self.pull.len(),
+            push: self.push.len(),
+            unresolved: self.unresolved.len(),
+        }
+    }
+
+    /// Logs the size of the plan as structured fields, to follow it over time.
+    pub fn log_counts(&self) {
+        let counts = self.counts();
+        tracing::debug!(
+            plan.pull_count = counts.pull,
+            plan.push_count = counts.push,
+            plan.unresolved_count = counts.unresolved,
+            "Built sync plan"
+        );
+    }
+
+    /// Checks that the plan can be executed, returning every problem found.
+    ///
+    /// Pull artifacts are keyed by local path, so two of them can never share one;
+    /// paths shared between a pull and a push artifact are reported instead.
+    pub fn validate(&self) -> Vec<PlanError> {
+        let mut errors = Vec::new();
+
+        let mut pull: Vec<_> = self.pull.iter().collect();
+        pull.sort_by(|a, b| a.0.cmp(b.0));
+        for (path, artifact) in pull {
+            let fields = [
+                ("group", &artifact.group),
+                ("artifact", &artifact.artifact),
+                ("version", &artifact.version),
+            ];
+            for (field, value) in fields {
+                if value.is_none() {
+                    errors.push(PlanError::MissingField {
+                        path: path.clone(),
+                        direction: Direction::Pull,
+                        field,
+                    });
+                }
+            }
+            if self.push.contains_key(path) {
+                errors.push(PlanError::SharedPath { path: path.clone() });
+            }
+        }
+
+        let mut push: Vec<_> = self.push.iter().collect();
+        push.sort_by(|a, b| a.0.cmp(b.0));
+        let mut coordinates = HashSet::new();
+        for (path, artifact) in push {
+            let fields = [("group", &artifact.group), ("artifact", &artifact.artifact)];
+            for (field, value) in fields {
+                if value.is_none() {
+                    errors.push(PlanError::MissingField {
+                        path: path.clone(),
+                        direction: Direction::Push,
+                        field,
+                    });
+                }
+            }
+            if let (Some(group), Some(id)) = (&artifact.group, &artifact.artifact) {
+                if !coordinates.insert((group, id)) {
+                    errors.push(PlanError::DuplicatePush {
+                        group: group.clone(),
+                        artifact: id.clone(),
+                    });
+                }
+            }
+        }
+
+        errors
+    }
+
+    /// Lists every artifact in the plan, sorted by local path.
+    pub fn describe(&self) -> Vec<PlanEntry> {
+        let pull = self.pull.iter().map(|(path, artifact)| PlanEntry {
+            path: path.clone(),
+            direction: Direction::Pull,
+            group: artifact.group.clone(),
+            artifact: artifact.artifact.clone(),
+            version: artifact.version.clone(),
+            version_source: Some(
+                artifact
+                    .version_source
+                    .clone()
+                    .unwrap_or(VersionSource::Latest),
+            ),
+        });
+        let push = self.push.iter().map(|(path, artifact)| PlanEntry {
+            path: path.clone(),
+            direction: Direction::Push,
+            group: artifact.group.clone(),
+            artifact: artifact.artifact.clone(),
+            version: None,
+            version_source: None,
+        });
+        let mut entries: Vec<PlanEntry> = pull.chain(push).collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        entries
+    }
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct PushArtifactRef {
     pub group: Option<String>,
     pub artifact: Option<String>,
+    pub url: Option<Url>,
     pub artifact_type: Option<ArtifactType>,
     pub name: Option<String>,
     pub description: Option<String>,
     pub labels: Option<Vec<String>>,
     pub properties: Option<HashMap<String, String>>,
+    pub bump_version: bool,
+    pub depends_on: Vec<PathBuf>,
+    pub on_push: Option<String>,
+    pub on_conflict: OnConflict,
 }
 
 #[derive(Debug, Default, Deserialize, Serialize)]
@@ -81,4 +223,194 @@ pub struct PullArtifactRef {
     pub artifact: Option<String>,
     pub artifact_type: Option<ArtifactType>,
     pub version: Option<String>,
+    pub version_source: Option<VersionSource>,
+    pub rename: Option<String>,
+    pub output_format: Option<OutputFormat>,
+    pub pull_references: bool,
+    pub on_pull: Option<String>,
+    pub strip_prefix: Option<String>,
+    pub add_prefix: Option<String>,
+    pub modified_on: Option<String>,
+    pub content_hash: Option<String>,
+}
+
+/// Where the version of a pull artifact comes from.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionSource {
+    Config,
+    Lockfile,
+    Latest,
+}
+
+impl Display for VersionSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            VersionSource::Config => "config pin",
+            VersionSource::Lockfile => "lockfile",
+            VersionSource::Latest => "latest",
+        };
+        s.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Pull,
+    Push,
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Direction::Pull => "pull",
+            Direction::Push => "push",
+        };
+        s.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlanCounts {
+    pub pull: usize,
+    pub push: usize,
+    pub unresolved: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlanEntry {
+    pub path: PathBuf,
+    pub direction: Direction,
+    pub group: Option<String>,
+    pub artifact: Option<String>,
+    pub version: Option<String>,
+    pub version_source: Option<VersionSource>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlanError {
+    MissingField {
+        path: PathBuf,
+        direction: Direction,
+        field: &'static str,
+    },
+    SharedPath {
+        path: PathBuf,
+    },
+    DuplicatePush {
+        group: String,
+        artifact: String,
+    },
+}
+
+impl Display for PlanError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanError::MissingField {
+                path,
+                direction,
+                field,
+            } => {
+                let hint = if *field == "version" {
+                    " (is the lockfile up to date?)"
+                } else {
+                    ""
+                };
+                write!(
+                    f,
+                    "{} artifact {} has no {}{}",
+                    direction,
+                    path.display(),
+                    field,
+                    hint
+                )
+            }
+            PlanError::SharedPath { path } => write!(
+                f,
+                "path {} is used by both a push and a pull artifact",
+                path.display()
+            ),
+            PlanError::DuplicatePush { group, artifact } => write!(
+                f,
+                "push artifact {}/{} is declared more than once",
+                group, artifact
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use warp::Filter;
+
+    use super::*;
+    use crate::client::Client;
+    use crate::context;
+    use crate::test_util::{self, temp_dir};
+
+    #[tokio::test]
+    async fn artifact_type_is_taken_from_the_lockfile_when_recorded() {
+        let workdir = temp_dir("plan-artifact-type");
+        // Written before types were recorded
+        std::fs::write(
+            workdir.join("apicurio-sync.lock"),
+            r#"{"pull": {"legacy.json": {"group": "g", "artifact": "legacy", "version": "1"}}}"#,
+        )
+        .unwrap();
+        let mut config = Config::parse(
+            r#"
+pull:
+  - group: g
+    artifact: schema
+    version: "1"
+    path: schema.avsc
+  - group: g
+    artifact: legacy
+    version: "1"
+    path: legacy.json
+"#,
+        )
+        .unwrap();
+        config.path = workdir.join("apicurio-sync.yaml");
+        let meta = warp::path!(
+            "apis"
+                / "registry"
+                / "v2"
+                / "groups"
+                / "g"
+                / "artifacts"
+                / String
+                / "versions"
+                / "1"
+                / "meta"
+        )
+        .map(|id: String| {
+            warp::reply::json(&serde_json::json!({
+                "groupId": "g",
+                "id": id,
+                "type": "AVRO",
+                "version": "1",
+                "createdBy": "test",
+                "createdOn": "2021-01-01T00:00:00Z",
+                "globalId": 1,
+                "contentId": 1,
+            }))
+        });
+        let provider = Arc::new(Client::new(test_util::serve(meta), HashMap::new()).v2());
+
+        let lockfile =
+            LockFile::try_load_for_config(&config, provider.as_ref(), &context::Auth::None)
+                .await
+                .unwrap();
+        let ctx = Context::new("test".to_string(), "http://localhost/".parse().unwrap());
+        let plan = Plan::new(ctx, provider)
+            .merge_with_config(&config)
+            .merge_with_lockfile(&lockfile);
+
+        let schema = &plan.pull[&PathBuf::from("schema.avsc")];
+        assert_eq!(schema.artifact_type, Some(ArtifactType::Avro));
+        let legacy = &plan.pull[&PathBuf::from("legacy.json")];
+        assert_eq!(legacy.artifact_type, None);
+    }
 }
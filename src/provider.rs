@@ -1,14 +1,19 @@
 use std::collections::HashMap;
 use std::fmt::{self, Display, Formatter};
+use std::io::Cursor;
+use std::str::FromStr;
 
 use async_trait::async_trait;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
+use tokio::io::AsyncRead;
 
 use crate::context;
 use crate::error::Error;
 
 #[async_trait]
-pub trait Provider {
+pub trait Provider: Send + Sync {
     async fn system_info(&self, auth: &context::Auth) -> Result<SystemInfo, Error>;
     async fn fetch_artifact_metadata(
         &self,
@@ -30,12 +35,156 @@ pub trait Provider {
         version: &str,
         auth: &context::Auth,
     ) -> Result<Vec<u8>, Error>;
+    /// Fetches content by the hex encoded SHA-256 of its raw bytes, regardless of the artifact
+    /// and version it belongs to.
+    async fn fetch_artifact_by_sha256(
+        &self,
+        hash: &str,
+        auth: &context::Auth,
+    ) -> Result<Vec<u8>, Error>;
     async fn push_artifact(
         &self,
         metadata: PushArtifactMetadata,
-        content: Vec<u8>,
+        content: PushContent,
+        auth: &context::Auth,
+    ) -> Result<ArtifactMetadata, Error>;
+    /// Lists every version of the artifact, oldest first.
+    async fn list_artifact_versions(
+        &self,
+        group_id: &str,
+        artifact_id: &str,
+        auth: &context::Auth,
+    ) -> Result<Vec<String>, Error>;
+    /// Lists the artifacts referenced by the version, e.g. the Protobuf files it imports.
+    async fn fetch_artifact_references(
+        &self,
+        group_id: &str,
+        artifact_id: &str,
+        version: &str,
+        auth: &context::Auth,
+    ) -> Result<Vec<ArtifactReference>, Error>;
+    async fn delete_artifact(
+        &self,
+        group_id: &str,
+        artifact_id: &str,
+        auth: &context::Auth,
+    ) -> Result<(), Error>;
+    async fn set_artifact_version_state(
+        &self,
+        group_id: &str,
+        artifact_id: &str,
+        version: &str,
+        state: ArtifactState,
         auth: &context::Auth,
     ) -> Result<(), Error>;
+    /// Checks whether the artifact exists, without downloading its content.
+    async fn artifact_exists(
+        &self,
+        group_id: &str,
+        artifact_id: &str,
+        auth: &context::Auth,
+    ) -> Result<ArtifactExistence, Error>;
+    async fn fetch_group_metadata(
+        &self,
+        group_id: &str,
+        auth: &context::Auth,
+    ) -> Result<GroupMetadata, Error>;
+    async fn create_group(
+        &self,
+        group_id: &str,
+        description: Option<String>,
+        auth: &context::Auth,
+    ) -> Result<GroupMetadata, Error>;
+    /// Deletes the group together with every artifact in it.
+    async fn delete_group(&self, group_id: &str, auth: &context::Auth) -> Result<(), Error>;
+
+    /// Resolves a SemVer range like `^1.2` to the highest matching version of the artifact.
+    /// Concrete versions are returned as they are, without contacting the registry.
+    async fn resolve_version_expression(
+        &self,
+        group_id: &str,
+        artifact_id: &str,
+        expression: &str,
+        auth: &context::Auth,
+    ) -> Result<String, Error> {
+        if !is_version_expression(expression) {
+            return Ok(expression.to_string());
+        }
+        let req = VersionReq::parse(expression).map_err(|err| {
+            Error::setup(format!("Invalid version range {}: {}", expression, err))
+        })?;
+        let versions = self
+            .list_artifact_versions(group_id, artifact_id, auth)
+            .await?;
+        // Versions that are not valid SemVer can never match a range
+        versions
+            .into_iter()
+            .filter_map(|version| Some((Version::parse(&version).ok()?, version)))
+            .filter(|(parsed, _)| req.matches(parsed))
+            .max_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(_, version)| version)
+            .ok_or_else(|| {
+                Error::setup(format!(
+                    "No version of {}/{} matches {}",
+                    group_id, artifact_id, expression
+                ))
+            })
+    }
+
+    /// Fetches the content of the version `expression` resolves to, along with that version.
+    async fn fetch_artifact_by_version_expression(
+        &self,
+        group_id: &str,
+        artifact_id: &str,
+        expression: &str,
+        auth: &context::Auth,
+    ) -> Result<(String, Vec<u8>), Error> {
+        let version = self
+            .resolve_version_expression(group_id, artifact_id, expression, auth)
+            .await?;
+        let content = self
+            .fetch_artifact_version(group_id, artifact_id, &version, auth)
+            .await?;
+        Ok((version, content))
+    }
+
+    /// Fetches every artifact in the group, following the pagination to the end.
+    async fn fetch_all_artifact_metadata(
+        &self,
+        group_id: &str,
+        auth: &context::Auth,
+    ) -> Result<Vec<SearchedArtifact>, Error>;
+
+    /// Fetches the content of many `(group, artifact, version)` triples at once.
+    /// By default they are fetched with `fetch_artifact_version`, up to `concurrency` at a time.
+    /// The limit is passed in by the caller, as it comes from the sync options rather than the provider,
+    /// and implementations that batch requests may ignore it.
+    async fn bulk_fetch_artifacts(
+        &self,
+        refs: &[(String, String, String)],
+        concurrency: usize,
+        auth: &context::Auth,
+    ) -> Result<HashMap<(String, String, String), Vec<u8>>, Error> {
+        let fetches: Vec<_> = refs
+            .iter()
+            .map(|key| async move {
+                let (group, artifact, version) = key;
+                let content = self
+                    .fetch_artifact_version(group, artifact, version, auth)
+                    .await?;
+                Ok::<_, Error>((key.clone(), content))
+            })
+            .collect();
+        stream::iter(fetches)
+            .buffer_unordered(concurrency.max(1))
+            .try_collect()
+            .await
+    }
+}
+
+/// Whether `version` is a SemVer range rather than a concrete version.
+pub fn is_version_expression(version: &str) -> bool {
+    version.contains(['~', '^', '*'])
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -69,7 +218,63 @@ impl Display for ArtifactType {
     }
 }
 
-#[derive(Debug)]
+impl ArtifactType {
+    /// The conventional file extension for artifacts of this type, if there is a single one.
+    /// OpenAPI and AsyncAPI documents can be either JSON or YAML, so they have none.
+    pub fn file_extension(&self) -> Option<&'static str> {
+        match self {
+            ArtifactType::Avro => Some("avsc"),
+            ArtifactType::Protobuf => Some("proto"),
+            ArtifactType::Json | ArtifactType::KConnect => Some("json"),
+            ArtifactType::GraphQL => Some("graphql"),
+            ArtifactType::Wsdl => Some("wsdl"),
+            ArtifactType::Xsd => Some("xsd"),
+            ArtifactType::OpenAPI | ArtifactType::AsyncAPI => None,
+        }
+    }
+}
+
+impl FromStr for ArtifactType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "AVRO" => Ok(ArtifactType::Avro),
+            "PROTOBUF" => Ok(ArtifactType::Protobuf),
+            "JSON" => Ok(ArtifactType::Json),
+            "KCONNECT" => Ok(ArtifactType::KConnect),
+            "OPENAPI" => Ok(ArtifactType::OpenAPI),
+            "ASYNCAPI" => Ok(ArtifactType::AsyncAPI),
+            "GRAPHQL" => Ok(ArtifactType::GraphQL),
+            "WSDL" => Ok(ArtifactType::Wsdl),
+            "XSD" => Ok(ArtifactType::Xsd),
+            _ => Err(format!("unknown artifact type `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ArtifactState {
+    #[default]
+    Enabled,
+    Disabled,
+    Deprecated,
+}
+
+impl Display for ArtifactState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ArtifactState::Enabled => "ENABLED",
+            ArtifactState::Disabled => "DISABLED",
+            ArtifactState::Deprecated => "DEPRECATED",
+        };
+        s.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(dead_code)]
 pub struct SystemInfo {
     pub name: String,
     pub description: String,
@@ -77,7 +282,8 @@ pub struct SystemInfo {
     pub built_on: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(dead_code)]
 pub struct ArtifactMetadata {
     pub group_id: String,
     pub id: String,
@@ -95,18 +301,38 @@ pub struct ArtifactMetadata {
     pub properties: HashMap<String, String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PushArtifactMetadata {
     pub group_id: String,
     pub artifact_id: String,
     pub name: Option<String>,
     pub description: Option<String>,
     pub artifact_type: Option<ArtifactType>,
+    pub version: Option<String>,
+    /// Create a version named after the current time when `version` is not set,
+    /// so that every push is distinguishable.
+    pub bump_version: bool,
     pub labels: Option<Vec<String>>,
     pub properties: Option<HashMap<String, String>>,
 }
 
-#[derive(Debug)]
+/// Content of an artifact to push, read lazily so that large artifacts do not have to fit in memory.
+pub struct PushContent {
+    pub reader: Box<dyn AsyncRead + Send + Sync + Unpin>,
+    pub len: u64,
+}
+
+impl PushContent {
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self {
+            len: bytes.len() as u64,
+            reader: Box::new(Cursor::new(bytes)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(dead_code)]
 pub struct ArtifactVersionMetadata {
     pub group_id: String,
     pub id: String,
@@ -120,14 +346,81 @@ pub struct ArtifactVersionMetadata {
     pub content_id: u64,
     pub labels: Vec<String>,
     pub properties: HashMap<String, String>,
+    pub state: ArtifactState,
 }
 
-pub struct NoopProvider;
+/// An artifact version referenced by another one, under the name the referencing content uses for it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ArtifactReference {
+    pub name: String,
+    pub group_id: String,
+    pub artifact_id: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArtifactExistence {
+    pub exists: bool,
+    /// The latest version of the artifact, when the registry tells it.
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupMetadata {
+    pub group_id: String,
+    pub description: Option<String>,
+    pub created_by: String,
+    pub created_on: String,
+    pub modified_by: String,
+    pub modified_on: String,
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct SearchedArtifact {
+    pub group_id: String,
+    pub id: String,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub artifact_type: ArtifactType,
+    pub created_by: String,
+    pub created_on: String,
+    pub modified_by: String,
+    pub modified_on: String,
+    pub labels: Vec<String>,
+}
+
+/// Provider for operations that never reach the registry.
+///
+/// By default calling it is a bug and panics. A `strict()` provider returns an error instead,
+/// for when no registry context is configured.
+#[derive(Default)]
+pub struct NoopProvider {
+    strict: bool,
+}
+
+impl NoopProvider {
+    pub fn strict() -> Self {
+        Self { strict: true }
+    }
+
+    fn unsupported<T>(&self, method: &str) -> Result<T, Error> {
+        if self.strict {
+            return Err(Error::setup(
+                "operation not supported without registry context",
+            ));
+        }
+        panic!(
+            "NoopProvider::{} called; this is a bug — NoopProvider should only be used for init",
+            method
+        )
+    }
+}
 
 #[async_trait]
 impl Provider for NoopProvider {
     async fn system_info(&self, _auth: &context::Auth) -> Result<SystemInfo, Error> {
-        unimplemented!()
+        self.unsupported("system_info")
     }
 
     async fn fetch_artifact_metadata(
@@ -136,7 +429,7 @@ impl Provider for NoopProvider {
         _artifact_id: &str,
         _auth: &context::Auth,
     ) -> Result<ArtifactMetadata, Error> {
-        unimplemented!()
+        self.unsupported("fetch_artifact_metadata")
     }
 
     async fn fetch_artifact_version_metadata(
@@ -146,7 +439,7 @@ impl Provider for NoopProvider {
         _version: &str,
         _auth: &context::Auth,
     ) -> Result<ArtifactVersionMetadata, Error> {
-        unimplemented!()
+        self.unsupported("fetch_artifact_version_metadata")
     }
 
     async fn fetch_artifact_version(
@@ -156,15 +449,182 @@ impl Provider for NoopProvider {
         _version: &str,
         _auth: &context::Auth,
     ) -> Result<Vec<u8>, Error> {
-        unimplemented!()
+        self.unsupported("fetch_artifact_version")
+    }
+
+    async fn fetch_artifact_by_sha256(
+        &self,
+        _hash: &str,
+        _auth: &context::Auth,
+    ) -> Result<Vec<u8>, Error> {
+        self.unsupported("fetch_artifact_by_sha256")
     }
 
     async fn push_artifact(
         &self,
         _metadata: PushArtifactMetadata,
-        _content: Vec<u8>,
+        _content: PushContent,
+        _auth: &context::Auth,
+    ) -> Result<ArtifactMetadata, Error> {
+        self.unsupported("push_artifact")
+    }
+
+    async fn fetch_all_artifact_metadata(
+        &self,
+        _group_id: &str,
+        _auth: &context::Auth,
+    ) -> Result<Vec<SearchedArtifact>, Error> {
+        self.unsupported("fetch_all_artifact_metadata")
+    }
+
+    async fn list_artifact_versions(
+        &self,
+        _group_id: &str,
+        _artifact_id: &str,
+        _auth: &context::Auth,
+    ) -> Result<Vec<String>, Error> {
+        self.unsupported("list_artifact_versions")
+    }
+
+    async fn fetch_artifact_references(
+        &self,
+        _group_id: &str,
+        _artifact_id: &str,
+        _version: &str,
+        _auth: &context::Auth,
+    ) -> Result<Vec<ArtifactReference>, Error> {
+        self.unsupported("fetch_artifact_references")
+    }
+
+    async fn delete_artifact(
+        &self,
+        _group_id: &str,
+        _artifact_id: &str,
         _auth: &context::Auth,
     ) -> Result<(), Error> {
-        unimplemented!()
+        self.unsupported("delete_artifact")
+    }
+
+    async fn set_artifact_version_state(
+        &self,
+        _group_id: &str,
+        _artifact_id: &str,
+        _version: &str,
+        _state: ArtifactState,
+        _auth: &context::Auth,
+    ) -> Result<(), Error> {
+        self.unsupported("set_artifact_version_state")
+    }
+
+    async fn artifact_exists(
+        &self,
+        _group_id: &str,
+        _artifact_id: &str,
+        _auth: &context::Auth,
+    ) -> Result<ArtifactExistence, Error> {
+        self.unsupported("artifact_exists")
+    }
+
+    async fn fetch_group_metadata(
+        &self,
+        _group_id: &str,
+        _auth: &context::Auth,
+    ) -> Result<GroupMetadata, Error> {
+        self.unsupported("fetch_group_metadata")
+    }
+
+    async fn create_group(
+        &self,
+        _group_id: &str,
+        _description: Option<String>,
+        _auth: &context::Auth,
+    ) -> Result<GroupMetadata, Error> {
+        self.unsupported("create_group")
+    }
+
+    async fn delete_group(&self, _group_id: &str, _auth: &context::Auth) -> Result<(), Error> {
+        self.unsupported("delete_group")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::de::DeserializeOwned;
+    use serde_json::json;
+
+    use super::*;
+
+    /// Serializes `value` to JSON and back, asserting that nothing is lost on the way.
+    fn roundtrip<T: Serialize + DeserializeOwned>(value: &T) -> serde_json::Value {
+        let json = serde_json::to_value(value).unwrap();
+        let back: T = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&back).unwrap(), json);
+        json
+    }
+
+    #[test]
+    fn system_info_roundtrips_through_json() {
+        let json = roundtrip(&SystemInfo {
+            name: "Apicurio Registry".to_string(),
+            description: "High performance, runtime registry".to_string(),
+            version: "2.0.1.Final".to_string(),
+            built_on: "2021-06-01T00:00:00Z".to_string(),
+        });
+        assert_eq!(json["built_on"], "2021-06-01T00:00:00Z");
+    }
+
+    #[test]
+    fn artifact_metadata_roundtrips_through_json() {
+        let json = roundtrip(&ArtifactMetadata {
+            group_id: "g".to_string(),
+            id: "a".to_string(),
+            name: Some("Schema".to_string()),
+            description: None,
+            artifact_type: ArtifactType::Avro,
+            version: "2".to_string(),
+            created_by: "alice".to_string(),
+            created_on: "2021-06-01T00:00:00Z".to_string(),
+            modified_by: "bob".to_string(),
+            modified_on: "2021-06-02T00:00:00Z".to_string(),
+            global_id: 12,
+            content_id: 7,
+            labels: vec!["team".to_string()],
+            properties: HashMap::from([("owner".to_string(), "payments".to_string())]),
+        });
+        assert_eq!(json["artifact_type"], "AVRO");
+        assert_eq!(json["description"], json!(null));
+    }
+
+    #[test]
+    fn artifact_version_metadata_roundtrips_through_json() {
+        let json = roundtrip(&ArtifactVersionMetadata {
+            group_id: "g".to_string(),
+            id: "a".to_string(),
+            name: None,
+            description: Some("First version".to_string()),
+            artifact_type: ArtifactType::Protobuf,
+            version: "1".to_string(),
+            created_by: "alice".to_string(),
+            created_on: "2021-06-01T00:00:00Z".to_string(),
+            global_id: 11,
+            content_id: 6,
+            labels: Vec::new(),
+            properties: HashMap::new(),
+            state: ArtifactState::Deprecated,
+        });
+        assert_eq!(json["state"], "DEPRECATED");
+    }
+
+    #[test]
+    fn group_metadata_roundtrips_through_json() {
+        let json = roundtrip(&GroupMetadata {
+            group_id: "g".to_string(),
+            description: Some("Payments".to_string()),
+            created_by: "alice".to_string(),
+            created_on: "2021-06-01T00:00:00Z".to_string(),
+            modified_by: "bob".to_string(),
+            modified_on: "2021-06-02T00:00:00Z".to_string(),
+        });
+        assert_eq!(json["group_id"], "g");
     }
 }
@@ -1,64 +1,1123 @@
-use std::path::Path;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::OsStr;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt, TryStreamExt};
 use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 
+use crate::checksum::{content_hash, file_hash, ChecksumAlgorithm};
+use crate::client::{classify_http_error, Client, HttpErrorKind};
+use crate::config::{OnConflict, OutputFormat};
 use crate::context;
 use crate::error::Error;
-use crate::plan::Plan;
-use crate::provider::{Provider, PushArtifactMetadata};
+use crate::lockfile::{LockFile, PushArtifactRecord};
+use crate::plan::{Plan, PullArtifactRef, PushArtifactRef};
+use crate::provider::{
+    ArtifactExistence, ArtifactMetadata, ArtifactState, ArtifactType, Provider,
+    PushArtifactMetadata, PushContent,
+};
 
+/// A pull artifact about to be written to disk.
+struct PullTarget<'a> {
+    path: &'a PathBuf,
+    destination: PathBuf,
+    key: (String, String, String),
+    artifact_type: ArtifactType,
+    output_format: OutputFormat,
+    content_hash: Option<&'a str>,
+    pull_references: bool,
+    on_pull: Option<&'a str>,
+}
+
+/// How many artifacts a sync pulled or pushed, for the summary printed once it completes.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SyncReport {
+    pub pulled: usize,
+    pub pushed: usize,
+    /// Artifacts left alone, e.g. unchanged push artifacts or disabled pull versions.
+    pub skipped: usize,
+    /// Push artifacts that failed or depend on one that did. Pulls stop at the first failure instead,
+    /// so they never count any.
+    pub failed: usize,
+}
+
+impl SyncReport {
+    pub fn merge(self, other: SyncReport) -> SyncReport {
+        SyncReport {
+            pulled: self.pulled + other.pulled,
+            pushed: self.pushed + other.pushed,
+            skipped: self.skipped + other.skipped,
+            failed: self.failed + other.failed,
+        }
+    }
+}
+
+/// Pulls every artifact in the plan, recording the content hash of each one written in the lockfile.
+/// Artifacts are fetched concurrently, up to the limit set in `sync_ctx`.
+#[allow(clippy::too_many_arguments)]
 pub async fn pull_artifacts(
-    provider: &impl Provider,
     plan: &Plan,
     workdir: &Path,
     auth: &context::Auth,
-) -> Result<(), Error> {
-    for (path, artifact) in &plan.pull {
-        let content = provider
-            .fetch_artifact_version(
-                artifact.group.as_ref().expect("artifact group"),
-                artifact.artifact.as_ref().expect("artifact id"),
-                artifact.version.as_ref().expect("artifact version"),
-                auth,
+    since: Option<DateTime<Utc>>,
+    sync_ctx: &SyncContext,
+    validate_content: bool,
+    atomic_writes: bool,
+    lockfile: &mut LockFile,
+) -> Result<SyncReport, Error> {
+    let provider = plan.provider.as_ref();
+    let checksum_algorithm = lockfile.checksum_algorithm();
+    let pull: Vec<(&PathBuf, &PullArtifactRef, (String, String, String))> = plan
+        .pull
+        .iter()
+        .filter(|(path, artifact)| match (since, modified_on(artifact)) {
+            (Some(since), Some(modified_on)) if modified_on <= since => {
+                tracing::debug!("Skipping {}: not modified since {}", path.display(), since);
+                false
+            }
+            _ => true,
+        })
+        .map(|(path, artifact)| {
+            (
+                path,
+                artifact,
+                (
+                    artifact.group.clone().expect("artifact group"),
+                    artifact.artifact.clone().expect("artifact id"),
+                    artifact.version.clone().expect("artifact version"),
+                ),
             )
-            .await?;
-        let destination = workdir.join(path);
-        tokio::fs::create_dir_all(&destination.parent().unwrap()).await?;
-        let mut file = File::create(&destination).await?;
-        file.write_all(&content).await?;
+        })
+        .collect();
+    let mut summary = SyncReport {
+        skipped: plan.pull.len() - pull.len(),
+        ..Default::default()
+    };
+
+    let concurrency = sync_ctx.max_concurrent_pulls;
+    let states: Vec<_> = stream::iter(pull.iter().map(|(_, _, (group, artifact, version))| {
+        provider.fetch_artifact_version_metadata(group, artifact, version, auth)
+    }))
+    .buffered(concurrency)
+    .try_collect()
+    .await
+    .map_err(|err| reported("pull artifacts", err))?;
+    let mut enabled = Vec::new();
+    for ((path, pull_ref, key), metadata) in pull.into_iter().zip(states) {
+        let (group, artifact, version) = &key;
+        match metadata.state {
+            ArtifactState::Disabled => {
+                tracing::warn!(
+                    "Skipping {}/{} version {}: the version is disabled",
+                    group,
+                    artifact,
+                    version
+                );
+                summary.skipped += 1;
+                continue;
+            }
+            ArtifactState::Deprecated => tracing::warn!(
+                "Pulling {}/{} version {}: the version is deprecated",
+                group,
+                artifact,
+                version
+            ),
+            ArtifactState::Enabled => {}
+        }
+        // Prefer the type recorded in the lockfile, falling back to the one just fetched
+        let artifact_type = pull_ref
+            .artifact_type
+            .as_ref()
+            .unwrap_or(&metadata.artifact_type);
+        let destination = match &pull_ref.rename {
+            Some(rename) => path.with_file_name(rename),
+            None => path.to_path_buf(),
+        };
+        let destination = with_prefixes(
+            &destination,
+            pull_ref.strip_prefix.as_deref(),
+            pull_ref.add_prefix.as_deref(),
+        );
+        enabled.push(PullTarget {
+            path,
+            destination: with_type_extension(&destination, artifact_type),
+            key,
+            artifact_type: artifact_type.clone(),
+            output_format: pull_ref.output_format.unwrap_or_default(),
+            content_hash: pull_ref.content_hash.as_deref(),
+            pull_references: pull_ref.pull_references,
+            on_pull: pull_ref.on_pull.as_deref(),
+        });
+    }
+
+    if atomic_writes {
+        for target in &enabled {
+            remove_stale_temp_files(&workdir.join(&target.destination)).await?;
+        }
+    }
+
+    let (by_hash, by_version): (Vec<&PullTarget>, Vec<&PullTarget>) = enabled
+        .iter()
+        // The registry only addresses content by its SHA-256
+        .partition(|target| {
+            target.content_hash.is_some() && checksum_algorithm == ChecksumAlgorithm::Sha256
+        });
+    let refs: Vec<(String, String, String)> =
+        by_version.iter().map(|target| target.key.clone()).collect();
+    let mut contents = provider
+        .bulk_fetch_artifacts(&refs, concurrency, auth)
+        .await
+        .map_err(|err| reported("pull artifacts", err))?;
+    let by_hash: Vec<_> = stream::iter(
+        by_hash
+            .iter()
+            .map(|target| fetch_by_hash(provider, target, auth)),
+    )
+    .buffer_unordered(concurrency)
+    .try_collect()
+    .await
+    .map_err(|err| reported("pull artifacts", err))?;
+    contents.extend(by_hash);
+
+    let mut referencing = Vec::new();
+    for target in enabled {
+        let PullTarget {
+            path,
+            destination,
+            key,
+            artifact_type,
+            output_format,
+            pull_references,
+            on_pull,
+            ..
+        } = target;
+        let content = &contents[&key];
+        if validate_content {
+            check_content(content, &artifact_type).map_err(|err| {
+                let (group, artifact, version) = &key;
+                Error::Parse(
+                    format!(
+                        "invalid {} content for {}/{} version {}: {}",
+                        artifact_type, group, artifact, version, err
+                    )
+                    .into(),
+                )
+            })?;
+        }
+        let hash = content_hash(content, checksum_algorithm);
+        let content = &reformat(content, output_format)?;
+        let destination = workdir.join(destination);
+        write_content(&destination, content, atomic_writes).await?;
+        if let Some(locked) = lockfile.pull.get_mut(path) {
+            locked.content_hash = Some(hash);
+        }
+        summary.pulled += 1;
+        if let Some(hook) = on_pull {
+            let (group, artifact, version) = &key;
+            let env = [
+                ("ARTIFACT_PATH", destination.as_os_str()),
+                ("ARTIFACT_GROUP", OsStr::new(group)),
+                ("ARTIFACT_ID", OsStr::new(artifact)),
+                ("ARTIFACT_VERSION", OsStr::new(version)),
+            ];
+            let status = run_hook(hook, workdir, &env).await?;
+            if !status.success() {
+                return Err(Error::setup(format!(
+                    "on_pull hook of {} failed with {}",
+                    path.display(),
+                    status
+                )));
+            }
+        }
+        if pull_references {
+            let dir = destination.parent().unwrap_or(workdir).to_path_buf();
+            referencing.push((dir, key));
+        }
+    }
+
+    pull_referenced_artifacts(provider, referencing, auth, atomic_writes).await?;
+    Ok(summary)
+}
+
+/// Runs an artifact hook with `sh -c` in `workdir`, with `env` describing the artifact.
+async fn run_hook(hook: &str, workdir: &Path, env: &[(&str, &OsStr)]) -> Result<ExitStatus, Error> {
+    tracing::debug!("Running hook `{}`", hook);
+    let status = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(hook)
+        .current_dir(workdir)
+        .envs(env.iter().copied())
+        .status()
+        .await?;
+    Ok(status)
+}
+
+/// Pulls the artifacts referenced by `referencing`, and the ones they reference in turn, into the
+/// directory of the artifact that first referenced them, under the names they are referenced by.
+/// They are always pulled as stored in the registry, and not recorded in the lockfile as the
+/// references already pin their versions.
+async fn pull_referenced_artifacts(
+    provider: &dyn Provider,
+    referencing: Vec<(PathBuf, (String, String, String))>,
+    auth: &context::Auth,
+    atomic_writes: bool,
+) -> Result<(), Error> {
+    let mut pulled = HashSet::new();
+    let mut queue: VecDeque<_> = referencing.into_iter().collect();
+    while let Some((dir, (group, artifact, version))) = queue.pop_front() {
+        let references = provider
+            .fetch_artifact_references(&group, &artifact, &version, auth)
+            .await
+            .map_err(|err| reported("pull artifact references", err))?;
+        for reference in references {
+            let name = Path::new(&reference.name);
+            if name.is_absolute() || name.components().any(|c| c == Component::ParentDir) {
+                tracing::warn!(
+                    "Skipping reference {} of {}/{}: it points outside of {}",
+                    reference.name,
+                    group,
+                    artifact,
+                    dir.display()
+                );
+                continue;
+            }
+            let destination = dir.join(name);
+            if !pulled.insert(destination.clone()) {
+                continue;
+            }
+            let content = provider
+                .fetch_artifact_version(
+                    &reference.group_id,
+                    &reference.artifact_id,
+                    &reference.version,
+                    auth,
+                )
+                .await
+                .map_err(|err| reported("pull artifact references", err))?;
+            write_content(&destination, &content, atomic_writes).await?;
+            tracing::info!(
+                "Pulled {}/{} version {}, referenced by {}/{}, to {}",
+                reference.group_id,
+                reference.artifact_id,
+                reference.version,
+                group,
+                artifact,
+                destination.display()
+            );
+            queue.push_back((
+                dir.clone(),
+                (reference.group_id, reference.artifact_id, reference.version),
+            ));
+        }
     }
+    Ok(())
+}
 
+async fn write_content(
+    destination: &Path,
+    content: &[u8],
+    atomic_writes: bool,
+) -> Result<(), Error> {
+    create_parent_dir(destination).await?;
+    if atomic_writes {
+        write_atomically(destination, content).await?;
+    } else {
+        let mut file = File::create(destination).await?;
+        file.write_all(content).await?;
+    }
     Ok(())
 }
 
+/// Fetches the content of `target` by the hash recorded in the lockfile, as content addressed
+/// responses can be cached anywhere along the way. Falls back to the version if the registry
+/// does not know the hash.
+async fn fetch_by_hash(
+    provider: &dyn Provider,
+    target: &PullTarget<'_>,
+    auth: &context::Auth,
+) -> Result<((String, String, String), Vec<u8>), Error> {
+    let hash = target.content_hash.expect("content hash");
+    let content = match provider.fetch_artifact_by_sha256(hash, auth).await {
+        Ok(content) => Some(content),
+        Err(Error::NotFound { .. }) => None,
+        Err(err) => return Err(err),
+    };
+    let content = match content {
+        Some(content) => content,
+        None => {
+            let (group, artifact, version) = &target.key;
+            provider
+                .fetch_artifact_version(group, artifact, version, auth)
+                .await?
+        }
+    };
+    Ok((target.key.clone(), content))
+}
+
+/// When the locked version of `artifact` was last modified, if the lockfile recorded it.
+fn modified_on(artifact: &PullArtifactRef) -> Option<DateTime<Utc>> {
+    let modified_on = artifact.modified_on.as_deref()?;
+    // The registry formats offsets without a colon, e.g. `2021-10-01T12:00:00+0000`
+    DateTime::parse_from_rfc3339(modified_on)
+        .or_else(|_| DateTime::parse_from_str(modified_on, "%Y-%m-%dT%H:%M:%S%z"))
+        .map(|modified_on| modified_on.with_timezone(&Utc))
+        .ok()
+}
+
+/// Checks that `content` looks like an artifact of `artifact_type`, to catch corrupted
+/// registry data before it is written to disk. Types without a known structure are not checked.
+fn check_content(content: &[u8], artifact_type: &ArtifactType) -> Result<(), String> {
+    match artifact_type {
+        ArtifactType::Json => {
+            serde_json::from_slice::<serde_json::Value>(content).map_err(|err| err.to_string())?;
+        }
+        ArtifactType::Avro => {
+            let schema: serde_json::Value =
+                serde_json::from_slice(content).map_err(|err| err.to_string())?;
+            // Primitive types and unions are valid schemas too, only records need a type
+            if schema.is_object() && schema.get("type").is_none() {
+                return Err("missing `type` field".to_string());
+            }
+        }
+        ArtifactType::OpenAPI => {
+            let document: serde_yaml::Value =
+                serde_yaml::from_slice(content).map_err(|err| err.to_string())?;
+            if document.get("openapi").is_none() && document.get("swagger").is_none() {
+                return Err("missing `openapi` or `swagger` field".to_string());
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Parses `content` and serializes it again in `format`. JSON is valid YAML, so both JSON and YAML
+/// content can be turned into pretty YAML.
+fn reformat(content: &[u8], format: OutputFormat) -> Result<Vec<u8>, Error> {
+    Ok(match format {
+        OutputFormat::Raw => content.to_vec(),
+        OutputFormat::PrettyJson => {
+            let value: serde_json::Value = serde_json::from_slice(content)?;
+            let mut pretty = serde_json::to_vec_pretty(&value)?;
+            pretty.push(b'\n');
+            pretty
+        }
+        OutputFormat::PrettyYaml => {
+            let value: serde_yaml::Value = serde_yaml::from_slice(content)?;
+            serde_yaml::to_vec(&value)?
+        }
+    })
+}
+
+/// Strips `strip` from the start of the file name of `path`, if present, then prepends `add`.
+/// A file name made only of the stripped prefix is left as it is.
+fn with_prefixes(path: &Path, strip: Option<&str>, add: Option<&str>) -> PathBuf {
+    let name = match path.file_name().and_then(OsStr::to_str) {
+        Some(name) => name,
+        None => return path.to_path_buf(),
+    };
+    let name = strip
+        .and_then(|prefix| name.strip_prefix(prefix))
+        .filter(|stripped| !stripped.is_empty())
+        .unwrap_or(name);
+    match add {
+        Some(prefix) => path.with_file_name(format!("{}{}", prefix, name)),
+        None => path.with_file_name(name),
+    }
+}
+
+/// Adds the usual file extension of `artifact_type` to `path`, unless it already has one.
+fn with_type_extension(path: &Path, artifact_type: &ArtifactType) -> PathBuf {
+    match artifact_type.file_extension() {
+        Some(extension) if path.extension().is_none() => path.with_extension(extension),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Options shared by all the pulls and pushes of a sync. Cloning it shares the push limit and the existence cache.
+#[derive(Clone)]
+pub struct SyncContext {
+    pub continue_on_error: bool,
+    pub force_push: bool,
+    /// Bounds how many artifacts are pushed at the same time, as registries may rate limit pushes.
+    push_permits: Arc<Semaphore>,
+    /// How many artifacts are pulled at the same time.
+    max_concurrent_pulls: usize,
+    /// Artifacts already checked for existence during this sync, by group and ID.
+    existing: Arc<Mutex<HashMap<(String, String), ArtifactExistence>>>,
+    /// Fetches push artifacts with a `url`, with the same HTTP settings as the registry client.
+    client: Client,
+}
+
+impl SyncContext {
+    pub fn new(
+        continue_on_error: bool,
+        force_push: bool,
+        max_concurrent_pushes: usize,
+        max_concurrent_pulls: usize,
+        client: Client,
+    ) -> Self {
+        Self {
+            continue_on_error,
+            force_push,
+            client,
+            push_permits: Arc::new(Semaphore::new(max_concurrent_pushes.max(1))),
+            max_concurrent_pulls: max_concurrent_pulls.max(1),
+            existing: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Checks whether the artifact exists in the registry, at most once per sync.
+    async fn artifact_exists(
+        &self,
+        provider: &dyn Provider,
+        group: &str,
+        id: &str,
+        auth: &context::Auth,
+    ) -> Result<ArtifactExistence, Error> {
+        let key = (group.to_string(), id.to_string());
+        if let Some(existence) = self.existing.lock().unwrap().get(&key) {
+            return Ok(existence.clone());
+        }
+        let existence = provider.artifact_exists(group, id, auth).await?;
+        self.existing.lock().unwrap().insert(key, existence.clone());
+        Ok(existence)
+    }
+}
+
+/// Pushes every artifact in the plan, recording what the registry assigned to each of them in the lockfile.
+/// Artifacts whose content did not change since the last push are skipped, unless `force_push` is set.
+///
+/// Artifacts are pushed after the ones they depend on, and concurrently otherwise, each in its own task
+/// and up to the limit set in `sync_ctx`. The lockfile is only updated once every task has completed.
+///
+/// What was pushed, skipped and failed is counted in `summary`, which is filled in even when an error
+/// is returned. With `continue_on_error`, failed artifacts are only counted there. Otherwise no more
+/// batches are started after a failure, but the pushes already running complete and are recorded
+/// before the errors are returned together.
 pub async fn push_artifacts(
-    provider: &impl Provider,
     plan: &Plan,
     workdir: &Path,
     auth: &context::Auth,
+    sync_ctx: &SyncContext,
+    lockfile: &mut LockFile,
+    summary: &mut SyncReport,
 ) -> Result<(), Error> {
-    for (path, artifact) in &plan.push {
-        let source = workdir.join(path);
-        let mut file = File::open(source).await?;
-        let mut content = Vec::new();
-        file.read_to_end(&mut content).await?;
-        provider
-            .push_artifact(
-                PushArtifactMetadata {
-                    group_id: artifact.group.clone().unwrap(),
-                    artifact_id: artifact.artifact.clone().unwrap(),
-                    name: artifact.name.clone(),
-                    description: artifact.description.clone(),
-                    artifact_type: artifact.artifact_type.clone(),
-                    labels: artifact.labels.clone(),
-                    properties: artifact.properties.clone(),
-                },
-                content,
-                auth,
-            )
+    let continue_on_error = sync_ctx.continue_on_error;
+    let checksum_algorithm = lockfile.checksum_algorithm();
+    let mut failed: HashSet<PathBuf> = HashSet::new();
+    let mut errors = Vec::new();
+    let mut records = Vec::new();
+    for batch in push_batches(plan)? {
+        if !errors.is_empty() {
+            break;
+        }
+        let mut pushes = JoinSet::new();
+        for path in batch {
+            let artifact = &plan.push[path];
+            if let Some(dependency) = artifact.depends_on.iter().find(|dep| failed.contains(*dep)) {
+                tracing::error!(
+                    "Skipping push of {}: its dependency {} failed",
+                    path.display(),
+                    dependency.display()
+                );
+                failed.insert(path.clone());
+                continue;
+            }
+            let last_hash = lockfile
+                .push
+                .get(path)
+                .filter(|_| !sync_ctx.force_push)
+                .map(|record| record.content_hash.clone());
+            let provider = plan.provider.clone();
+            let path = path.clone();
+            let artifact = artifact.clone();
+            let workdir = workdir.to_path_buf();
+            let auth = auth.clone();
+            let sync_ctx = sync_ctx.clone();
+            pushes.spawn(async move {
+                let res = push_artifact(
+                    provider.as_ref(),
+                    &path,
+                    &artifact,
+                    &workdir,
+                    &auth,
+                    last_hash.as_deref(),
+                    checksum_algorithm,
+                    &sync_ctx,
+                )
+                .await;
+                (path, res)
+            });
+        }
+
+        while let Some(joined) = pushes.join_next().await {
+            let (path, res) = joined.expect("push task panicked");
+            match res {
+                Ok(PushOutcome::Unchanged) => {
+                    tracing::info!("Skipping push of {} (content unchanged)", path.display());
+                    summary.skipped += 1;
+                }
+                Ok(PushOutcome::Exists(version)) => {
+                    let version = version
+                        .map(|version| format!(" (version {})", version))
+                        .unwrap_or_default();
+                    tracing::info!(
+                        "Skipping push of {}: the artifact already exists in the registry{}",
+                        path.display(),
+                        version
+                    );
+                    summary.skipped += 1;
+                }
+                Ok(PushOutcome::HookFailed(status)) => {
+                    tracing::error!(
+                        "Skipping push of {}: its on_push hook failed with {}",
+                        path.display(),
+                        status
+                    );
+                    failed.insert(path);
+                }
+                Ok(PushOutcome::Pushed(pushed, hash)) => {
+                    tracing::info!(
+                        "Pushed {} as {}/{} version {} (global ID {})",
+                        path.display(),
+                        pushed.group_id,
+                        pushed.id,
+                        pushed.version,
+                        pushed.global_id
+                    );
+                    records.push((path, pushed, hash, Utc::now()));
+                    summary.pushed += 1;
+                }
+                Err(err) => {
+                    report(&format!("push {}", path.display()), &err);
+                    failed.insert(path);
+                    if !continue_on_error {
+                        errors.push(err);
+                    }
+                }
+            }
+        }
+    }
+
+    for (path, pushed, hash, pushed_at) in records {
+        lockfile.push.insert(
+            path,
+            PushArtifactRecord {
+                group: pushed.group_id,
+                artifact: pushed.id,
+                version: pushed.version,
+                global_id: pushed.global_id,
+                content_id: pushed.content_id,
+                content_hash: hash,
+                pushed_at,
+            },
+        );
+    }
+    summary.failed = failed.len();
+    lockfile.save().await?;
+    if !errors.is_empty() {
+        return Err(Error::Multiple(errors));
+    }
+    Ok(())
+}
+
+/// Logs a failed registry operation, hinting at where to look depending on what went wrong.
+/// Network problems are usually temporary, so they are only logged as warnings.
+fn report(action: &str, err: &Error) {
+    let kind = match err {
+        Error::Http { err, .. } => classify_http_error(err),
+        Error::Unauthorized { .. } => HttpErrorKind::Auth,
+        Error::NotFound { .. } => HttpErrorKind::NotFound,
+        _ => HttpErrorKind::Unknown,
+    };
+    match kind {
+        HttpErrorKind::Network => tracing::warn!(
+            "Failed to {}: {} (is the registry reachable? Check your network connection, VPN or firewall)",
+            action,
+            err
+        ),
+        // `Error::Unauthorized` already explains what to do
+        HttpErrorKind::Auth if !matches!(err, Error::Unauthorized { .. }) => tracing::error!(
+            "Failed to {}: {} (check the context credentials or run `apicurio-sync context login` again)",
+            action,
+            err
+        ),
+        _ => tracing::error!("Failed to {}: {}", action, err),
+    }
+}
+
+fn reported(action: &str, err: Error) -> Error {
+    report(action, &err);
+    err
+}
+
+/// Splits the push artifacts into batches that only depend on artifacts in earlier batches.
+fn push_batches(plan: &Plan) -> Result<Vec<Vec<&PathBuf>>, Error> {
+    let mut remaining: Vec<&PathBuf> = plan.push.keys().collect();
+    remaining.sort();
+    let mut pushed: HashSet<&PathBuf> = HashSet::new();
+    let mut batches = Vec::new();
+    while !remaining.is_empty() {
+        let (ready, blocked): (Vec<&PathBuf>, Vec<&PathBuf>) =
+            remaining.into_iter().partition(|path| {
+                plan.push[*path]
+                    .depends_on
+                    .iter()
+                    .all(|dep| pushed.contains(dep) || !plan.push.contains_key(dep))
+            });
+        if ready.is_empty() {
+            let cycle: Vec<String> = find_cycle(plan, &blocked)
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect();
+            return Err(Error::setup(format!(
+                "Push artifacts depend on each other in a cycle: {}",
+                cycle.join(" -> ")
+            )));
+        }
+        pushed.extend(ready.iter().copied());
+        batches.push(ready);
+        remaining = blocked;
+    }
+    Ok(batches)
+}
+
+/// Follows the dependencies between `blocked` artifacts, all of which depend on another one of them,
+/// until one repeats.
+fn find_cycle<'a>(plan: &'a Plan, blocked: &[&'a PathBuf]) -> Vec<&'a PathBuf> {
+    let mut chain = vec![blocked[0]];
+    loop {
+        let last = chain[chain.len() - 1];
+        let next = plan.push[last]
+            .depends_on
+            .iter()
+            .find(|dep| blocked.contains(dep))
+            .expect("blocked artifact without blocked dependencies");
+        if let Some(start) = chain.iter().position(|path| *path == next) {
+            let mut cycle = chain.split_off(start);
+            cycle.push(next);
+            return cycle;
+        }
+        chain.push(next);
+    }
+}
+
+/// What happened to a push artifact, when it did not fail.
+enum PushOutcome {
+    /// The content hash matches the last pushed one.
+    Unchanged,
+    Pushed(Box<ArtifactMetadata>, String),
+    /// The artifact exists and is not to be updated, with its latest version if known.
+    Exists(Option<String>),
+    HookFailed(ExitStatus),
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn push_artifact(
+    provider: &dyn Provider,
+    path: &Path,
+    artifact: &PushArtifactRef,
+    workdir: &Path,
+    auth: &context::Auth,
+    last_hash: Option<&str>,
+    checksum_algorithm: ChecksumAlgorithm,
+    sync_ctx: &SyncContext,
+) -> Result<PushOutcome, Error> {
+    let group = artifact.group.clone().unwrap();
+    let id = artifact.artifact.clone().unwrap();
+    let source = workdir.join(path);
+    // Checked first, as there is no point in reading content that would be discarded
+    if artifact.on_conflict == OnConflict::Skip {
+        let _permit = sync_ctx
+            .push_permits
+            .acquire()
+            .await
+            .expect("push semaphore closed");
+        let existence = sync_ctx
+            .artifact_exists(provider, &group, &id, auth)
             .await?;
+        if existence.exists {
+            return Ok(PushOutcome::Exists(existence.version));
+        }
+    }
+    if let Some(hook) = &artifact.on_push {
+        let env = [
+            ("ARTIFACT_PATH", source.as_os_str()),
+            ("ARTIFACT_GROUP", OsStr::new(&group)),
+            ("ARTIFACT_ID", OsStr::new(&id)),
+        ];
+        let status = run_hook(hook, workdir, &env).await?;
+        if !status.success() {
+            return Ok(PushOutcome::HookFailed(status));
+        }
+    }
+    let not_found = |err: std::io::Error| match err.kind() {
+        ErrorKind::NotFound => Error::setup(format!(
+            "push artifact file not found: {} (artifact {}/{})",
+            source.display(),
+            group,
+            id
+        )),
+        _ => err.into(),
+    };
+    // Local files are hashed and pushed without reading them in memory, as they can be large
+    let (remote, hash) = match &artifact.url {
+        Some(url) => {
+            let content = sync_ctx.client.fetch_url(url).await?;
+            let hash = content_hash(&content, checksum_algorithm);
+            (Some(content), hash)
+        }
+        None => (
+            None,
+            file_hash(&source, checksum_algorithm)
+                .await
+                .map_err(not_found)?,
+        ),
+    };
+    if last_hash == Some(hash.as_str()) {
+        return Ok(PushOutcome::Unchanged);
+    }
+    let _permit = sync_ctx
+        .push_permits
+        .acquire()
+        .await
+        .expect("push semaphore closed");
+    let content = match remote {
+        Some(content) => PushContent::from_bytes(content),
+        None => {
+            let file = File::open(&source).await.map_err(not_found)?;
+            PushContent {
+                len: file.metadata().await?.len(),
+                reader: Box::new(file),
+            }
+        }
+    };
+    let pushed = provider
+        .push_artifact(
+            PushArtifactMetadata {
+                group_id: group,
+                artifact_id: id,
+                name: artifact.name.clone(),
+                description: artifact.description.clone(),
+                artifact_type: artifact.artifact_type.clone(),
+                version: None,
+                bump_version: artifact.bump_version,
+                labels: artifact.labels.clone(),
+                properties: artifact.properties.clone(),
+            },
+            content,
+            auth,
+        )
+        .await?;
+    Ok(PushOutcome::Pushed(Box::new(pushed), hash))
+}
+
+/// Writes `content` to a temporary file next to `destination`, then renames it over `destination`,
+/// so that an interrupted pull never leaves a partially written artifact behind.
+///
+/// The temporary file is named `{destination}.{pid}-{n}.tmp`, so concurrent writes never share one,
+/// and is removed if anything fails before the rename.
+async fn write_atomically(destination: &Path, content: &[u8]) -> Result<(), Error> {
+    static NEXT_TMP: AtomicUsize = AtomicUsize::new(0);
+
+    let mut tmp = destination.as_os_str().to_owned();
+    tmp.push(format!(
+        ".{}-{}.tmp",
+        std::process::id(),
+        NEXT_TMP.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp = PathBuf::from(tmp);
+    let res = async {
+        let mut file = File::create(&tmp).await?;
+        file.write_all(content).await?;
+        file.sync_all().await?;
+        drop(file);
+        tokio::fs::rename(&tmp, destination).await
+    }
+    .await;
+    if let Err(err) = res {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        return Err(err.into());
     }
+    Ok(())
+}
 
+/// Removes the `{destination}.*.tmp` files left over by atomic writes of interrupted runs.
+async fn remove_stale_temp_files(destination: &Path) -> Result<(), Error> {
+    let (dir, name) = match (destination.parent(), destination.file_name()) {
+        (Some(dir), Some(name)) => (dir, name.to_string_lossy()),
+        _ => return Ok(()),
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    let prefix = format!("{}.", name);
+    while let Some(entry) = entries.next_entry().await? {
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+        if file_name.starts_with(&prefix) && file_name.ends_with(".tmp") {
+            tracing::debug!("Removing stale temporary file {}", entry.path().display());
+            match tokio::fs::remove_file(entry.path()).await {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+    }
     Ok(())
 }
+
+/// Creates the parent directory of `path`, if any. Directories created concurrently by
+/// another pull are not an error.
+pub async fn create_parent_dir(path: &Path) -> Result<(), Error> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => return Ok(()),
+    };
+    match tokio::fs::create_dir_all(parent).await {
+        Err(err) if err.kind() != ErrorKind::AlreadyExists => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use url::Url;
+    use warp::Filter;
+
+    use crate::config::Config;
+    use crate::context::Context;
+    use crate::provider::NoopProvider;
+    use crate::test_util::{self, temp_dir};
+
+    use super::*;
+
+    async fn lockfile(workdir: &Path) -> LockFile {
+        let mut config = Config::parse("{}").unwrap();
+        config.path = workdir.join("apicurio-sync.yaml");
+        LockFile::load_for_config(&config).await.unwrap()
+    }
+
+    fn sync_ctx(continue_on_error: bool) -> SyncContext {
+        let client = Client::new(Url::parse("http://localhost/").unwrap(), HashMap::new());
+        SyncContext::new(continue_on_error, false, 5, 5, client)
+    }
+
+    /// Serves a registry that accepts every push after `delay`, and returns a provider pointing to it.
+    fn registry(delay: Duration) -> Arc<dyn Provider> {
+        let create = warp::post()
+            .and(warp::path!(
+                "apis" / "registry" / "v2" / "groups" / String / "artifacts"
+            ))
+            .and(warp::header::<String>("x-registry-artifactid"))
+            .and_then(move |group: String, id: String| async move {
+                tokio::time::sleep(delay).await;
+                Ok::<_, warp::Rejection>(warp::reply::json(&serde_json::json!({
+                    "groupId": group,
+                    "id": id,
+                    "type": "JSON",
+                    "version": "1",
+                    "createdBy": "test",
+                    "createdOn": "2021-01-01T00:00:00Z",
+                    "modifiedBy": "test",
+                    "modifiedOn": "2021-01-01T00:00:00Z",
+                    "globalId": 1,
+                    "contentId": 1,
+                })))
+            });
+        let update = warp::put().map(warp::reply);
+        let url = test_util::serve(create.or(update));
+        Arc::new(Client::new(url, HashMap::new()).v2())
+    }
+
+    fn plan(provider: Arc<dyn Provider>, push: &[&str]) -> Plan {
+        let ctx = Context::new("test".to_string(), Url::parse("http://localhost/").unwrap());
+        let mut plan = Plan::new(ctx, provider);
+        for path in push {
+            plan.push.insert(
+                PathBuf::from(path),
+                PushArtifactRef {
+                    group: Some("g".to_string()),
+                    artifact: Some(path.trim_end_matches(".json").to_string()),
+                    ..Default::default()
+                },
+            );
+        }
+        plan
+    }
+
+    #[tokio::test]
+    async fn push_of_missing_file_names_the_file_and_artifact() {
+        let workdir = temp_dir("push-missing-file");
+        let plan = plan(Arc::new(NoopProvider::default()), &["missing.json"]);
+        let path = PathBuf::from("missing.json");
+        let res = push_artifact(
+            plan.provider.as_ref(),
+            &path,
+            &plan.push[&path],
+            &workdir,
+            &context::Auth::None,
+            None,
+            ChecksumAlgorithm::Sha256,
+            &sync_ctx(false),
+        )
+        .await;
+
+        match res {
+            Err(Error::Setup(msg)) => {
+                assert!(msg.contains(&workdir.join("missing.json").display().to_string()));
+                assert!(msg.contains("g/missing"));
+            }
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("push of a missing file succeeded"),
+        }
+    }
+
+    #[tokio::test]
+    async fn push_of_missing_file_with_continue_on_error_counts_as_failed() {
+        let workdir = temp_dir("push-missing-file-continue");
+        let plan = plan(Arc::new(NoopProvider::default()), &["missing.json"]);
+        let mut lockfile = lockfile(&workdir).await;
+        let mut summary = SyncReport::default();
+        push_artifacts(
+            &plan,
+            &workdir,
+            &context::Auth::None,
+            &sync_ctx(true),
+            &mut lockfile,
+            &mut summary,
+        )
+        .await
+        .expect("push aborted");
+
+        assert_eq!(summary.failed, 1);
+        assert_eq!(summary.pushed, 0);
+        assert!(lockfile.push.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_push_lets_running_pushes_complete_and_records_them() {
+        let workdir = temp_dir("push-failure-records-running");
+        std::fs::write(workdir.join("slow.json"), "{}").unwrap();
+        let plan = plan(
+            registry(Duration::from_millis(200)),
+            &["missing.json", "slow.json"],
+        );
+        let mut lockfile = lockfile(&workdir).await;
+        let mut summary = SyncReport::default();
+        let res = push_artifacts(
+            &plan,
+            &workdir,
+            &context::Auth::None,
+            &sync_ctx(false),
+            &mut lockfile,
+            &mut summary,
+        )
+        .await;
+
+        match res {
+            Err(Error::Multiple(errors)) => assert_eq!(errors.len(), 1),
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("push of a missing file succeeded"),
+        }
+        assert_eq!((summary.pushed, summary.failed), (1, 1));
+        let record = &lockfile.push[&PathBuf::from("slow.json")];
+        assert_eq!(
+            (record.group.as_str(), record.artifact.as_str()),
+            ("g", "slow")
+        );
+        assert!(!lockfile.push.contains_key(&PathBuf::from("missing.json")));
+    }
+
+    /// Names of the files in `dir`, sorted.
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn stale_temp_files_of_the_destination_are_removed() {
+        let workdir = temp_dir("stale-temp-files");
+        for name in [
+            "a.json.123-0.tmp",
+            "a.json.tmp",
+            "ab.json.1-0.tmp",
+            "b.json.1-0.tmp",
+        ] {
+            std::fs::write(workdir.join(name), "{").unwrap();
+        }
+
+        remove_stale_temp_files(&workdir.join("a.json"))
+            .await
+            .unwrap();
+        write_atomically(&workdir.join("a.json"), b"{}")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            file_names(&workdir),
+            ["a.json", "ab.json.1-0.tmp", "b.json.1-0.tmp"]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_atomic_write_removes_its_temp_file() {
+        let workdir = temp_dir("failed-atomic-write");
+        // Renaming a file over a non-empty directory fails
+        std::fs::create_dir_all(workdir.join("a.json").join("nested")).unwrap();
+
+        write_atomically(&workdir.join("a.json"), b"{}")
+            .await
+            .expect_err("rename over a directory");
+
+        assert_eq!(file_names(&workdir), ["a.json"]);
+    }
+
+    #[tokio::test]
+    async fn concurrent_writes_into_the_same_new_directory_all_succeed() {
+        let workdir = temp_dir("concurrent-parent-dir");
+        let destinations: Vec<PathBuf> = (0..32)
+            .map(|i| {
+                workdir
+                    .join("new")
+                    .join("nested")
+                    .join(format!("{}.json", i))
+            })
+            .collect();
+
+        let writes =
+            destinations
+                .iter()
+                .enumerate()
+                .map(|(i, destination)| async move {
+                    write_content(destination, b"{}", i % 2 == 0).await
+                });
+        let results = futures::future::join_all(writes).await;
+
+        for (destination, res) in destinations.iter().zip(results) {
+            res.unwrap_or_else(|err| panic!("write {}: {}", destination.display(), err));
+            assert_eq!(std::fs::read(destination).unwrap(), b"{}");
+        }
+    }
+}
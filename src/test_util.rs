@@ -0,0 +1,41 @@
+//! Helpers shared by the tests of several modules.
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+
+use url::Url;
+use warp::Filter;
+
+/// An empty directory under the system temp directory, unique to the test.
+pub fn temp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("apicurio-sync-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Serves `routes` on an ephemeral port until the test runtime shuts down, and returns its URL.
+pub fn serve<F>(routes: F) -> Url
+where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: warp::Reply,
+{
+    let (addr, server) = warp::serve(routes)
+        .try_bind_ephemeral(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .expect("bind test server");
+    tokio::spawn(server);
+    Url::parse(&format!("http://{}/", addr)).unwrap()
+}
+
+/// Answers `GET /apis/registry/v2/system/info` like a registry would.
+pub fn system_info() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone
+{
+    warp::path!("apis" / "registry" / "v2" / "system" / "info").map(|| {
+        warp::reply::json(&serde_json::json!({
+            "name": "registry",
+            "description": "test",
+            "version": "2",
+            "builtOn": "now",
+        }))
+    })
+}